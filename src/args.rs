@@ -0,0 +1,465 @@
+use clap::{ArgAction, Parser};
+use serde::Deserialize;
+use std::time::Duration;
+
+use std::path::PathBuf;
+use xtream2m3u::chan_group::{M3uOptions, diff_dir, m3u_dir, m3u_file_name};
+use xtream2m3u::diff::DiffFormat;
+use xtream2m3u::manifest::StaleAction;
+use xtream2m3u::notify::{Email, SmtpSecurity, Targets};
+use xtream2m3u::retention::Retention;
+use xtream2m3u::retry::RetryPolicy;
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    Text,
+    Json,
+}
+
+#[derive(clap::Subcommand, Debug, Clone)]
+pub enum Command {
+    #[command(about = "Show the current and next programme of a live channel")]
+    NowNext {
+        #[arg(help = "Name or stream id of the channel")]
+        channel: String,
+    },
+}
+
+#[derive(Parser, Debug, Clone)]
+#[command(version, about, long_about=None)]
+pub struct Args {
+    #[arg(short, long, env = "XTREAM_SERVER", default_value = "", hide_default_value = true)]
+    pub server: String,
+    #[arg(short, long, env = "XTREAM_USERNAME", default_value = "", hide_default_value = true)]
+    pub username: String,
+    #[arg(
+        short,
+        long,
+        env = "XTREAM_PASSWORD",
+        hide_env_values = true,
+        default_value = "",
+        hide_default_value = true
+    )]
+    pub password: String,
+    #[arg(short, long, help = "Config file to read profiles from")]
+    pub config: Option<String>,
+    #[arg(long, help = "Use the named profile from the config file")]
+    pub profile: Option<String>,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+    #[arg(
+        short,
+        long,
+        help = "Append .ts to stream URLs",
+		num_args = 0..=1,
+        default_value = "",
+        default_missing_value = ".ts",
+    )]
+    pub ts: String,
+    #[arg(
+        short,
+        long,
+        help = "Create a M3U for each VOD category",
+        action = ArgAction::Set,
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true",
+        default_value_t = false,
+        hide_default_value = true,
+        value_name = "BOOL",
+    )]
+    pub vod: bool,
+    #[arg(
+        long,
+        help = "Create a M3U for Series",
+        action = ArgAction::Set,
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true",
+        default_value_t = false,
+        hide_default_value = true,
+        value_name = "BOOL",
+    )]
+    pub series: bool,
+    #[arg(short = 'T', long, help = "Modify the stream URL for use in TVHeadend")]
+    pub tvheadend_remux: bool,
+    #[arg(
+        short,
+        long,
+        help = "Do not add a header to the M3U files",
+        action = ArgAction::Set,
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true",
+        default_value_t = false,
+        hide_default_value = true,
+        value_name = "BOOL",
+    )]
+    pub no_header: bool,
+    #[arg(
+        short,
+        long,
+        help = "Create M3U/Diff for live channels",
+        action = ArgAction::Set,
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true",
+        default_value_t = false,
+        hide_default_value = true,
+        value_name = "BOOL",
+    )]
+    pub live: bool,
+    #[arg(short, long)]
+    pub account_info: bool,
+    #[arg(
+        long,
+        global = true,
+        value_enum,
+        help = "Show the account information and now-next as text or JSON",
+        default_value_t = OutputFormat::Text
+    )]
+    pub format: OutputFormat,
+    #[arg(
+        short,
+        long,
+        action = ArgAction::Set,
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true",
+        default_value_t = false,
+        hide_default_value = true,
+        value_name = "BOOL",
+    )]
+    pub diff: bool,
+    #[arg(
+        long,
+        value_enum,
+        help = "Also save the changes of each run as JSON or NDJSON, with a run summary"
+    )]
+    pub diff_format: Option<DiffFormat>,
+    #[arg(
+        long,
+        help = "Download the XMLTV guide of the live channels to epg.xml next to their M3U files",
+        action = ArgAction::Set,
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true",
+        default_value_t = false,
+        hide_default_value = true,
+        value_name = "BOOL",
+    )]
+    pub epg: bool,
+    #[arg(
+        long,
+        help = "Compress the guide to epg.xml.gz",
+        action = ArgAction::Set,
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true",
+        default_value_t = false,
+        hide_default_value = true,
+        value_name = "BOOL",
+    )]
+    pub epg_gzip: bool,
+    #[arg(long, help = "Leave out programmes that ended more than this many days ago")]
+    pub epg_past_days: Option<u32>,
+    #[arg(long, help = "Leave out programmes that start more than this many days from now")]
+    pub epg_future_days: Option<u32>,
+    #[arg(
+        long,
+        help = "Rewrite the programme times as local times in this timezone, or \"server\" for the timezone of the server"
+    )]
+    pub epg_timezone: Option<String>,
+    #[arg(long, help = "Add the channels of this local XMLTV file that the provider has no guide for (can be repeated)")]
+    pub epg_source: Vec<String>,
+    #[arg(long, help = "TOML file that maps channel ids of --epg-source files to provider stream ids")]
+    pub epg_map: Option<String>,
+    #[arg(
+        long,
+        help = "Find EPG ids for live channels without one by their name, saved in epg_matches.toml",
+        action = ArgAction::Set,
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true",
+        default_value_t = false,
+        hide_default_value = true,
+        value_name = "BOOL",
+    )]
+    pub epg_auto_match: bool,
+    #[arg(long, help = "How similar, from 0 to 1, a name must be for --epg-auto-match", default_value_t = 0.9)]
+    pub epg_match_threshold: f64,
+    #[arg(
+        long,
+        help = "Save an HTML report of the changes of each run",
+        action = ArgAction::Set,
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true",
+        default_value_t = false,
+        hide_default_value = true,
+        value_name = "BOOL",
+    )]
+    pub html_report: bool,
+    #[arg(
+        long,
+        help = "Keep an Atom feed of new movies and episodes in the output directory",
+        action = ArgAction::Set,
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true",
+        default_value_t = false,
+        hide_default_value = true,
+        value_name = "BOOL",
+    )]
+    pub feed: bool,
+    #[arg(long, help = "Number of entries to keep in the feed", default_value_t = 100)]
+    pub feed_entries: usize,
+    #[arg(long, help = "Keep the diff files of only the last N runs (0 keeps all)", default_value_t = 0)]
+    pub keep_diff_runs: usize,
+    #[arg(long, help = "Remove diff files older than this many days (0 keeps all)", default_value_t = 0)]
+    pub max_diff_age: u64,
+    #[arg(
+        long,
+        help = "Add removed text diffs to a monthly changelog instead of only deleting them",
+        action = ArgAction::Set,
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true",
+        default_value_t = false,
+        hide_default_value = true,
+        value_name = "BOOL",
+    )]
+    pub rollup: bool,
+    #[arg(
+        short,
+        long,
+        help = "Create M3U files",
+        action = ArgAction::Set,
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true",
+        default_value_t = false,
+        hide_default_value = true,
+        value_name = "BOOL",
+    )]
+    pub m3u: bool,
+    #[arg(
+        short = 'S',
+        long,
+        help = "Create a single M3U file",
+        action = ArgAction::Set,
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true",
+        default_value_t = false,
+        hide_default_value = true,
+        value_name = "BOOL",
+    )]
+    pub single_m3u: bool,
+    #[arg(
+        short,
+        long,
+        help = "Where to save M3U/Diff files",
+        default_value = "."
+    )]
+    pub output_dir: String,
+    #[arg(long, help = "Where to save diff files and snapshots [default: the output directory]")]
+    pub diff_dir: Option<String>,
+    #[arg(
+        long,
+        help = "Number of category/series requests to run at the same time",
+        default_value_t = 1,
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..)
+    )]
+    pub concurrency: usize,
+    #[arg(
+        short,
+        long,
+        help = "Get all streams of a type with one request instead of one request per category",
+        action = ArgAction::Set,
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true",
+        default_value_t = false,
+        hide_default_value = true,
+        value_name = "BOOL",
+    )]
+    pub bulk: bool,
+    #[arg(
+        long,
+        help = "Also get the full stream list without --bulk, to find streams with no known category",
+        action = ArgAction::Set,
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true",
+        default_value_t = false,
+        hide_default_value = true,
+        value_name = "BOOL",
+    )]
+    pub find_uncategorized: bool,
+    #[arg(
+        long,
+        help = "Group name for streams with no known category",
+        default_value = "Uncategorized"
+    )]
+    pub uncategorized_name: String,
+    #[arg(long, help = "Number of previous versions of each M3U file to keep", default_value_t = 0)]
+    pub backups: usize,
+    #[arg(
+        long,
+        value_enum,
+        help = "What to do with M3U files of categories that no longer exist",
+        default_value_t = StaleAction::Keep
+    )]
+    pub stale: StaleAction,
+    #[arg(long, help = "Only list the stale M3U files that --stale would remove")]
+    pub stale_dry_run: bool,
+    #[arg(
+        long,
+        help = "Do not update the files of a type if more than this percent of its channels would be removed",
+        default_value_t = 50.0
+    )]
+    pub max_deleted: f64,
+    #[arg(short, long, help = "Update the files even if --max-deleted is exceeded")]
+    pub force: bool,
+    #[arg(long, help = "POST a JSON summary of the run to this URL (can be repeated)")]
+    pub webhook: Vec<String>,
+    #[arg(long, help = "Send a notification to this ntfy topic URL (can be repeated)")]
+    pub ntfy: Vec<String>,
+    #[arg(long, help = "Send a notification to this Gotify message URL, with ?token= (can be repeated)")]
+    pub gotify: Vec<String>,
+    #[arg(long, help = "SMTP server to send email notifications through")]
+    pub smtp_host: Option<String>,
+    #[arg(long, help = "SMTP port [default: 465 for tls, 587 for starttls, 25 for none]")]
+    pub smtp_port: Option<u16>,
+    #[arg(
+        long,
+        value_enum,
+        help = "How to encrypt the SMTP connection",
+        default_value_t = SmtpSecurity::Starttls
+    )]
+    pub smtp_security: SmtpSecurity,
+    #[arg(long, help = "User name for SMTP authentication")]
+    pub smtp_user: Option<String>,
+    #[arg(
+        long,
+        env = "XTREAM_SMTP_PASSWORD",
+        hide_env_values = true,
+        help = "Password for SMTP authentication"
+    )]
+    pub smtp_password: Option<String>,
+    #[arg(long, help = "Sender address of email notifications [default: --smtp-user]")]
+    pub smtp_from: Option<String>,
+    #[arg(long, help = "Send email notifications to this address (can be repeated)")]
+    pub smtp_to: Vec<String>,
+    #[arg(
+        long,
+        help = "Notify after every run, not only when something changed or went wrong",
+        action = ArgAction::Set,
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true",
+        default_value_t = false,
+        hide_default_value = true,
+        value_name = "BOOL",
+    )]
+    pub notify_always: bool,
+    #[arg(
+        long,
+        help = "Warn and exit with status 4 when the account expires within this many days or is not Active"
+    )]
+    pub warn_days: Option<i64>,
+    #[arg(long, help = "Seconds before a request to the server times out", default_value_t = 30)]
+    pub timeout: u64,
+    #[arg(long, help = "Times to retry a failed request", default_value_t = 3)]
+    pub retries: u32,
+    #[arg(long, help = "Seconds to wait before the first retry, doubled on each retry", default_value_t = 1.0)]
+    pub backoff: f64,
+    #[arg(long, help = "Longest time in seconds to wait between retries, unless the server asks for longer with Retry-After", default_value_t = 30)]
+    pub max_backoff: u64,
+}
+
+impl Args {
+    // Whether the full stream list of each type is fetched, for --bulk or to find the
+    // streams with no known category
+    pub fn full_list(&self) -> bool {
+        self.bulk || self.find_uncategorized
+    }
+
+    pub fn retry_policy(&self) -> RetryPolicy {
+        RetryPolicy {
+            timeout: Duration::from_secs(self.timeout),
+            retries: self.retries,
+            backoff: Duration::from_secs_f64(self.backoff.max(0.0)),
+            max_backoff: Duration::from_secs(self.max_backoff),
+        }
+    }
+
+    pub fn m3u_options(&self) -> M3uOptions {
+        M3uOptions {
+            server: self.server.clone(),
+            username: self.username.clone(),
+            password: self.password.clone(),
+            output_dir: self.output_dir.clone(),
+            single_m3u: self.single_m3u,
+            no_header: self.no_header,
+            backups: self.backups,
+        }
+    }
+
+    pub fn m3u_dir(&self, group_type: &str) -> PathBuf {
+        m3u_dir(&self.output_dir, group_type)
+    }
+
+    pub fn m3u_file_name(&self, group_name: &str) -> String {
+        m3u_file_name(group_name, self.single_m3u)
+    }
+
+    // The top of the diff tree, which is the output directory unless --diff-dir is given
+    pub fn diff_base(&self) -> &str {
+        self.diff_dir.as_deref().unwrap_or(&self.output_dir)
+    }
+
+    pub fn diff_dir(&self, group_type: &str) -> PathBuf {
+        diff_dir(self.diff_base(), group_type)
+    }
+
+    pub fn retention(&self) -> Retention {
+        Retention {
+            keep_runs: self.keep_diff_runs,
+            max_age_days: self.max_diff_age,
+            rollup: self.rollup,
+        }
+    }
+
+    pub fn notify_targets(&self) -> Targets {
+        Targets {
+            webhooks: self.webhook.clone(),
+            ntfy: self.ntfy.clone(),
+            gotify: self.gotify.clone(),
+            email: self.email(),
+        }
+    }
+
+    fn email(&self) -> Option<Email> {
+        let host = self.smtp_host.clone()?;
+        if self.smtp_to.is_empty() {
+            return None;
+        }
+        let from = self
+            .smtp_from
+            .clone()
+            .or(self.smtp_user.clone())
+            .unwrap_or_else(|| "xtream2m3u@localhost".to_string());
+        Some(Email {
+            host,
+            port: self.smtp_port,
+            security: self.smtp_security,
+            user: self.smtp_user.clone(),
+            password: self.smtp_password.clone(),
+            from,
+            to: self.smtp_to.clone(),
+        })
+    }
+}
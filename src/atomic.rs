@@ -0,0 +1,91 @@
+use std::fs::{File, OpenOptions, copy, remove_file, rename};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+// A file that is written next to its destination and only renamed into place by commit(),
+// so readers never see a half written file. Dropping it without commit() leaves the
+// destination untouched.
+#[derive(Debug)]
+pub struct AtomicFile {
+    dest: PathBuf,
+    tmp: PathBuf,
+    file: Option<File>,
+}
+
+impl AtomicFile {
+    pub fn create(dest: &Path) -> io::Result<AtomicFile> {
+        let name = dest.file_name().unwrap_or_default().to_string_lossy();
+        let tmp = dest.with_file_name(format!(".{name}.tmp"));
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&tmp)?;
+        Ok(AtomicFile {
+            dest: dest.to_path_buf(),
+            tmp,
+            file: Some(file),
+        })
+    }
+
+    // Replace the destination, first keeping up to `backups` previous versions of it as
+    // dest.1 (newest) to dest.N (oldest)
+    pub fn commit(mut self, backups: usize) -> io::Result<()> {
+        if let Some(file) = self.file.take() {
+            file.sync_all()?;
+        }
+        if backups > 0 && self.dest.exists() {
+            rotate_backups(&self.dest, backups)?;
+        }
+        rename(&self.tmp, &self.dest)
+    }
+}
+
+impl Write for AtomicFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self.file {
+            Some(ref mut f) => f.write(buf),
+            None => Err(io::Error::other("file already committed")),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.file {
+            Some(ref mut f) => f.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for AtomicFile {
+    fn drop(&mut self) {
+        if self.file.take().is_some() {
+            let _ = remove_file(&self.tmp);
+        }
+    }
+}
+
+fn backup_name(dest: &Path, n: usize) -> PathBuf {
+    let name = dest.file_name().unwrap_or_default().to_string_lossy();
+    dest.with_file_name(format!("{name}.{n}"))
+}
+
+// The current file is copied rather than renamed so it stays in place until the new
+// version replaces it
+fn rotate_backups(dest: &Path, backups: usize) -> io::Result<()> {
+    let _ = remove_file(backup_name(dest, backups));
+    for n in (1..backups).rev() {
+        let from = backup_name(dest, n);
+        if from.exists() {
+            rename(&from, backup_name(dest, n + 1))?;
+        }
+    }
+    copy(dest, backup_name(dest, 1))?;
+    Ok(())
+}
+
+pub fn write_atomic(dest: &Path, contents: &str, backups: usize) -> io::Result<()> {
+    let mut f = AtomicFile::create(dest)?;
+    f.write_all(contents.as_bytes())?;
+    f.commit(backups)
+}
@@ -0,0 +1,166 @@
+use static_str_ops::static_format;
+use std::{fs::create_dir_all, io::Write, path::PathBuf};
+
+use crate::atomic::AtomicFile;
+use crate::types::Channel;
+
+pub fn m3u_dir(output_dir: &str, group_type: &str) -> PathBuf {
+    [output_dir, &format!("{group_type}_m3u")].iter().collect()
+}
+
+pub fn m3u_file_name(group_name: &str, single_m3u: bool) -> String {
+    match single_m3u {
+        false => sanitise_file_name::sanitise(static_format!("{group_name}.m3u")).to_string(),
+        true => sanitise_file_name::sanitise(static_format!("all.m3u")).to_string(),
+    }
+}
+
+// Where the diff files and channel snapshots for a type of stream are kept, below the top of
+// the diff tree
+pub fn diff_dir(diff_base: &str, group_type: &str) -> PathBuf {
+    [diff_base, &format!("{group_type}_diff")].iter().collect()
+}
+
+// How the M3U files are written
+#[derive(Debug, Clone, Default)]
+pub struct M3uOptions {
+    pub server: String,
+    pub username: String,
+    pub password: String,
+    pub output_dir: String,
+    pub single_m3u: bool,
+    pub no_header: bool,
+    // Previous versions of each file to keep
+    pub backups: usize,
+}
+
+#[derive(Debug)]
+pub struct ChanGroup {
+    options: M3uOptions,
+    group_name: String,
+    file_name: String,
+    file_created: bool,
+    m3u_dir: PathBuf,
+    handle: Option<AtomicFile>,
+    group_type: String,
+    // The first error writing the file, which stops it being moved into place
+    error: Option<std::io::Error>,
+}
+
+impl ChanGroup {
+    pub fn new(options: M3uOptions, group_name: String, group_type: String) -> ChanGroup {
+        let m3u_dir = m3u_dir(&options.output_dir, &group_type);
+        let file_name = m3u_file_name(&group_name, options.single_m3u);
+
+        ChanGroup {
+            options,
+            group_name,
+            file_name,
+            file_created: false,
+            m3u_dir,
+            handle: None,
+            group_type,
+            error: None,
+        }
+    }
+
+    pub fn group_name(&self) -> &str {
+        &self.group_name
+    }
+
+    pub fn create_file(&mut self) -> std::io::Result<()> {
+        let result = self.open();
+        self.remember(result)
+    }
+
+    fn open(&mut self) -> std::io::Result<()> {
+        if let Ok(false) = std::fs::exists(&self.m3u_dir) {
+            println!("Creating {:?}", self.m3u_dir);
+            let _ = create_dir_all(&self.m3u_dir);
+        }
+        
+        if !self.file_created {
+            println!("Creating {:?}", self.file_name);
+            self.handle = Some(AtomicFile::create(
+                &self.m3u_dir.join(self.file_name.clone()),
+            )?);
+            if !self.options.no_header && let Some(ref mut h) = self.handle {
+                writeln!(h, "#EXTM3U")?;
+            }
+            self.file_created = true;
+        } else {
+            println!("Using {:?}", self.handle);
+        }
+        Ok(())
+    }
+
+    // Keep the first error so that finish leaves the previous file in place
+    fn remember(&mut self, result: std::io::Result<()>) -> std::io::Result<()> {
+        if let Err(e) = &result
+            && self.error.is_none()
+        {
+            self.error = Some(std::io::Error::new(e.kind(), e.to_string()));
+        }
+        result
+    }
+
+    // Move the finished M3U into place, keeping the configured number of backups. A file that
+    // could not be completely written is discarded instead
+    pub fn finish(&mut self) -> std::io::Result<()> {
+        let handle = self.handle.take();
+        if let Some(e) = self.error.take() {
+            return Err(e);
+        }
+        match handle {
+            Some(h) => h.commit(self.options.backups),
+            None => Ok(()),
+        }
+    }
+
+    pub fn add_channel(&mut self, chan: &Channel) -> std::io::Result<()> {
+        let result = self.write_channel(chan);
+        self.remember(result)
+    }
+
+    fn write_channel(&mut self, chan: &Channel) -> std::io::Result<()> {
+        let Channel {
+            stream_id,
+            name: chan_name,
+            epg_id,
+            logo: stream_icon,
+            ext,
+            ..
+        } = chan;
+        let gname = chan.group_title();
+        if let Some(ref mut h) = self.handle {
+            writeln!(
+                h,
+                "#EXTINF:-1 tvg-id=\"{}\" tvg-name=\"{}\" tvg-logo=\"{}\" group-title=\"{}\",\
+                {}",
+                epg_id.trim_start_matches("\"").trim_end_matches("\""),
+                chan_name.trim_start_matches("\"").trim_end_matches("\""),
+                stream_icon.trim_start_matches("\"").trim_end_matches("\""),
+                gname.trim_start_matches("\"").trim_end_matches("\""),
+                chan_name.trim_start_matches("\"").trim_end_matches("\""),
+            )?;
+
+            let mut server = self.options.server.clone();
+            if self.group_type != "live" {
+                server += &*format!("/{}", self.group_type);
+            }
+            //if !ext.is_empty() {
+            //    ext = format!(".{}", ext);
+            //}
+            writeln!(
+                h,
+                "{}/{}/{}/{}{}",
+                server,
+                self.options.username,
+                self.options.password,
+                stream_id,
+                ext
+            )?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,320 @@
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::error::{Error, Result};
+use crate::retry::RetryPolicy;
+use crate::types::series::{SerEnum, read_series};
+use crate::types::{
+    AccountInfo, Category, Listing, LiveStream, SeriesStream, ShortEpg, VodInfo, VodStream,
+};
+
+// Access to the player_api.php endpoints of an Xtream Codes provider
+#[derive(Clone)]
+pub struct XtreamClient {
+    http: reqwest::Client,
+    server: String,
+    username: String,
+    password: String,
+    retry: RetryPolicy,
+    on_retry: Option<RetryHook>,
+}
+
+// Leaves out the password and the hook, which has nothing to show
+impl fmt::Debug for XtreamClient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("XtreamClient")
+            .field("server", &self.server)
+            .field("username", &self.username)
+            .field("retry", &self.retry)
+            .finish_non_exhaustive()
+    }
+}
+
+pub type RetryHook = Arc<dyn Fn(&Retry) + Send + Sync>;
+
+// A request that failed and is tried again after delay, passed to the on_retry hook so the
+// caller decides how to show it
+#[derive(Debug)]
+pub struct Retry<'a> {
+    pub request: String,
+    pub attempt: u32,
+    pub retries: u32,
+    pub delay: Duration,
+    pub error: &'a Error,
+}
+
+impl fmt::Display for Retry<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Retrying {} ({}/{}) in {:.1}s: {}",
+            self.request,
+            self.attempt,
+            self.retries,
+            self.delay.as_secs_f32(),
+            self.error
+        )
+    }
+}
+
+impl XtreamClient {
+    pub fn new(server: &str, username: &str, password: &str) -> XtreamClient {
+        XtreamClient {
+            http: reqwest::Client::new(),
+            server: server.trim_end_matches('/').to_string(),
+            username: username.to_string(),
+            password: password.to_string(),
+            retry: RetryPolicy::default(),
+            on_retry: None,
+        }
+    }
+
+    pub fn with_retry(mut self, retry: RetryPolicy) -> XtreamClient {
+        self.retry = retry;
+        self
+    }
+
+    // Called before each retry of a failed request
+    pub fn on_retry(mut self, on_retry: impl Fn(&Retry) + Send + Sync + 'static) -> XtreamClient {
+        self.on_retry = Some(Arc::new(on_retry));
+        self
+    }
+
+    pub fn server(&self) -> &str {
+        &self.server
+    }
+
+    async fn get(&self, path: &str, params: &[(&str, &str)]) -> Result<Vec<u8>> {
+        let mut attempt = 0;
+        loop {
+            let request = self
+                .http
+                .get(format!("{}/{path}", self.server))
+                .query(&[("username", &self.username), ("password", &self.password)])
+                .query(params)
+                .timeout(self.retry.timeout);
+            // The URL has the password in its query, so it is left out of the errors
+            let (err, retry_after) = match request.send().await {
+                Ok(resp) if resp.status().is_success() => match resp.bytes().await {
+                    Ok(body) => return Ok(body.to_vec()),
+                    Err(e) => (Error::Http(e.without_url()), None),
+                },
+                Ok(resp) => (Error::Status(resp.status()), retry_after(&resp)),
+                Err(e) => (Error::Http(e.without_url()), None),
+            };
+            if attempt >= self.retry.retries || !err.is_transient() {
+                return Err(err);
+            }
+            let delay = self.retry.delay(attempt, retry_after);
+            attempt += 1;
+            if let Some(on_retry) = &self.on_retry {
+                on_retry(&Retry {
+                    request: describe(path, params),
+                    attempt,
+                    retries: self.retry.retries,
+                    delay,
+                    error: &err,
+                });
+            }
+            tokio::time::sleep(delay).await;
+        }
+    }
+
+    async fn get_json<T: DeserializeOwned>(&self, params: &[(&str, &str)]) -> Result<T> {
+        let body = self.get("player_api.php", params).await?;
+        Ok(serde_json::from_slice(&body)?)
+    }
+
+    async fn get_listing<T: DeserializeOwned>(
+        &self,
+        params: &[(&str, &str)],
+    ) -> Result<Listing<T>> {
+        let values: Vec<Value> = self.get_json(params).await?;
+        Ok(Listing::parse(values))
+    }
+
+    pub async fn account_info(&self) -> Result<AccountInfo> {
+        self.get_json(&[]).await
+    }
+
+    pub async fn live_categories(&self) -> Result<Listing<Category>> {
+        self.get_listing(&[("action", "get_live_categories")]).await
+    }
+
+    pub async fn vod_categories(&self) -> Result<Listing<Category>> {
+        self.get_listing(&[("action", "get_vod_categories")]).await
+    }
+
+    pub async fn series_categories(&self) -> Result<Listing<Category>> {
+        self.get_listing(&[("action", "get_series_categories")])
+            .await
+    }
+
+    pub async fn live_streams(&self, category_id: &str) -> Result<Listing<LiveStream>> {
+        self.get_listing(&[("action", "get_live_streams"), ("category_id", category_id)])
+            .await
+    }
+
+    pub async fn vod_streams(&self, category_id: &str) -> Result<Listing<VodStream>> {
+        self.get_listing(&[("action", "get_vod_streams"), ("category_id", category_id)])
+            .await
+    }
+
+    pub async fn all_live_streams(&self) -> Result<Listing<LiveStream>> {
+        self.get_listing(&[("action", "get_live_streams")]).await
+    }
+
+    pub async fn all_vod_streams(&self) -> Result<Listing<VodStream>> {
+        self.get_listing(&[("action", "get_vod_streams")]).await
+    }
+
+    pub async fn all_series(&self) -> Result<Listing<SeriesStream>> {
+        self.get_listing(&[("action", "get_series")]).await
+    }
+
+    pub async fn series(&self, category_id: &str) -> Result<Listing<SeriesStream>> {
+        self.get_listing(&[("action", "get_series"), ("category_id", category_id)])
+            .await
+    }
+
+    pub async fn series_info(&self, series_id: &str) -> Result<SerEnum> {
+        let j: Value = self
+            .get_json(&[("action", "get_series_info"), ("series_id", series_id)])
+            .await?;
+        match read_series(j.to_string()) {
+            SerEnum::None => Err(Error::Shape("series info")),
+            series => Ok(series),
+        }
+    }
+
+    pub async fn vod_info(&self, vod_id: &str) -> Result<VodInfo> {
+        self.get_json(&[("action", "get_vod_info"), ("vod_id", vod_id)])
+            .await
+    }
+
+    // The next few programmes of a live channel, starting with the current one
+    pub async fn short_epg(&self, stream_id: &str, limit: usize) -> Result<ShortEpg> {
+        let limit = limit.to_string();
+        self.get_json(&[
+            ("action", "get_short_epg"),
+            ("stream_id", stream_id),
+            ("limit", &limit),
+        ])
+        .await
+    }
+
+    // The XMLTV guide of every live channel, which can be many megabytes
+    pub async fn xmltv(&self) -> Result<Vec<u8>> {
+        self.get("xmltv.php", &[]).await
+    }
+}
+
+fn retry_after(resp: &reqwest::Response) -> Option<Duration> {
+    resp.headers()
+        .get(reqwest::header::RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse()
+        .ok()
+        .map(Duration::from_secs)
+}
+
+// The request without credentials, for messages
+fn describe(path: &str, params: &[(&str, &str)]) -> String {
+    match params {
+        [] if path == "xmltv.php" => "EPG".to_string(),
+        [] => "account information".to_string(),
+        _ => params
+            .iter()
+            .map(|(k, v)| format!("{k}={v}"))
+            .collect::<Vec<_>>()
+            .join("&"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn errors_leave_out_the_password() {
+        // A port nothing listens on
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let client = XtreamClient::new(&format!("http://127.0.0.1:{port}"), "alice", "S3CRET")
+            .with_retry(RetryPolicy {
+                retries: 0,
+                ..RetryPolicy::default()
+            });
+        let err = client.account_info().await.unwrap_err();
+        assert!(matches!(err, Error::Http(_)));
+        let text = format!("{err} {err:?}");
+        assert!(!text.contains("password="), "{text}");
+        assert!(!text.contains("S3CRET"), "{text}");
+    }
+
+    #[tokio::test]
+    async fn on_retry_can_capture() {
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let seen = Arc::new(std::sync::atomic::AtomicU32::new(0));
+        let counter = seen.clone();
+        let client = XtreamClient::new(&format!("http://127.0.0.1:{port}"), "alice", "S3CRET")
+            .with_retry(RetryPolicy {
+                retries: 2,
+                backoff: Duration::from_millis(1),
+                max_backoff: Duration::from_millis(1),
+                ..RetryPolicy::default()
+            })
+            .on_retry(move |_| {
+                counter.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            });
+        assert!(client.account_info().await.is_err());
+        assert_eq!(seen.load(std::sync::atomic::Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn vod_info_is_lenient() {
+        let info: VodInfo = serde_json::from_str(
+            r#"{"info": {"name": "Film", "duration_secs": "5400", "backdrop_path": "b.jpg",
+                "tmdb_id": 42, "process": true},
+                "movie_data": {"stream_id": 7, "container_extension": "mkv"}}"#,
+        )
+        .unwrap();
+        assert_eq!(info.info.name, "Film");
+        assert_eq!(info.info.duration_secs, Some(5400));
+        assert_eq!(info.info.backdrop_path, vec!["b.jpg"]);
+        assert_eq!(info.info.tmdb_id.as_deref(), Some("42"));
+        assert_eq!(info.info.extra["process"], Value::Bool(true));
+        assert_eq!(info.movie_data.unwrap().stream_id, "7");
+
+        let empty: VodInfo = serde_json::from_str(r#"{"info": [], "movie_data": []}"#).unwrap();
+        assert!(empty.info.name.is_empty());
+        assert!(empty.movie_data.is_none());
+    }
+
+    #[test]
+    fn account_info_keeps_what_the_server_sent() {
+        let account: AccountInfo = serde_json::from_str(
+            r#"{"user_info": {"status": "Active", "max_connections": "2"},
+                "server_info": {"url": "example.com", "process": true}}"#,
+        )
+        .unwrap();
+        let out = serde_json::to_value(&account).unwrap();
+        assert_eq!(out["server_info"]["process"], Value::Bool(true));
+        assert!(out["server_info"].get("https_port").is_none());
+        assert!(out["server_info"].get("time_now").is_none());
+        assert!(out["user_info"].get("auth").is_none());
+        assert_eq!(account.user_info.max_connections, Some(2));
+    }
+}
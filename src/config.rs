@@ -0,0 +1,208 @@
+use clap::ArgMatches;
+use clap::parser::ValueSource;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::args::{Args, OutputFormat};
+use xtream2m3u::diff::DiffFormat;
+use xtream2m3u::manifest::StaleAction;
+use xtream2m3u::notify::SmtpSecurity;
+
+// Unknown keys are rejected so a misspelled option is not silently ignored
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    pub default_profile: Option<String>,
+    #[serde(default)]
+    pub profiles: HashMap<String, Profile>,
+}
+
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    pub server: Option<String>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub output_dir: Option<String>,
+    pub diff_dir: Option<String>,
+    pub ts: Option<String>,
+    pub live: Option<bool>,
+    pub vod: Option<bool>,
+    pub series: Option<bool>,
+    pub m3u: Option<bool>,
+    pub diff: Option<bool>,
+    pub diff_format: Option<DiffFormat>,
+    pub format: Option<OutputFormat>,
+    pub epg: Option<bool>,
+    pub epg_gzip: Option<bool>,
+    pub epg_past_days: Option<u32>,
+    pub epg_future_days: Option<u32>,
+    pub epg_timezone: Option<String>,
+    pub epg_source: Option<Vec<String>>,
+    pub epg_map: Option<String>,
+    pub epg_auto_match: Option<bool>,
+    pub epg_match_threshold: Option<f64>,
+    pub keep_diff_runs: Option<usize>,
+    pub max_diff_age: Option<u64>,
+    pub rollup: Option<bool>,
+    pub html_report: Option<bool>,
+    pub feed: Option<bool>,
+    pub feed_entries: Option<usize>,
+    pub single_m3u: Option<bool>,
+    pub no_header: Option<bool>,
+    pub bulk: Option<bool>,
+    pub find_uncategorized: Option<bool>,
+    pub uncategorized_name: Option<String>,
+    pub concurrency: Option<usize>,
+    pub max_deleted: Option<f64>,
+    pub backups: Option<usize>,
+    pub stale: Option<StaleAction>,
+    pub webhook: Option<Vec<String>>,
+    pub ntfy: Option<Vec<String>>,
+    pub gotify: Option<Vec<String>>,
+    pub notify_always: Option<bool>,
+    pub smtp_host: Option<String>,
+    pub smtp_port: Option<u16>,
+    pub smtp_security: Option<SmtpSecurity>,
+    pub smtp_user: Option<String>,
+    pub smtp_password: Option<String>,
+    pub smtp_from: Option<String>,
+    pub smtp_to: Option<Vec<String>>,
+    pub warn_days: Option<i64>,
+    pub timeout: Option<u64>,
+    pub retries: Option<u32>,
+    pub backoff: Option<f64>,
+    pub max_backoff: Option<u64>,
+}
+
+// ~/.config/xtream2m3u/config.toml on Linux, the platform equivalent elsewhere
+pub fn default_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|d| d.join("xtream2m3u").join("config.toml"))
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Config, String> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| format!("Unable to read config file {path:?}: {e}"))?;
+        toml::from_str(&contents).map_err(|e| format!("Error in config file {path:?}: {e}"))
+    }
+
+    pub fn profile(&self, name: &str) -> Result<&Profile, String> {
+        self.profiles
+            .get(name)
+            .ok_or_else(|| format!("Profile {name:?} not found in config file"))
+    }
+}
+
+// Only used when the value was not given on the command line or through the environment
+fn from_default(matches: &ArgMatches, id: &str) -> bool {
+    matches!(matches.value_source(id), None | Some(ValueSource::DefaultValue))
+}
+
+fn set<T>(matches: &ArgMatches, id: &str, value: Option<T>, arg: &mut T) {
+    if let Some(v) = value
+        && from_default(matches, id)
+    {
+        *arg = v;
+    }
+}
+
+impl Profile {
+    pub fn apply(&self, args: &mut Args, matches: &ArgMatches) {
+        let strings = [
+            ("server", &self.server, &mut args.server),
+            ("username", &self.username, &mut args.username),
+            ("password", &self.password, &mut args.password),
+            ("output_dir", &self.output_dir, &mut args.output_dir),
+            ("ts", &self.ts, &mut args.ts),
+            ("uncategorized_name", &self.uncategorized_name, &mut args.uncategorized_name),
+        ];
+        for (id, value, arg) in strings {
+            set(matches, id, value.clone(), arg);
+        }
+
+        let flags = [
+            ("live", self.live, &mut args.live),
+            ("vod", self.vod, &mut args.vod),
+            ("series", self.series, &mut args.series),
+            ("m3u", self.m3u, &mut args.m3u),
+            ("diff", self.diff, &mut args.diff),
+            ("single_m3u", self.single_m3u, &mut args.single_m3u),
+            ("no_header", self.no_header, &mut args.no_header),
+            ("bulk", self.bulk, &mut args.bulk),
+            ("find_uncategorized", self.find_uncategorized, &mut args.find_uncategorized),
+            ("rollup", self.rollup, &mut args.rollup),
+            ("epg", self.epg, &mut args.epg),
+            ("epg_gzip", self.epg_gzip, &mut args.epg_gzip),
+            ("epg_auto_match", self.epg_auto_match, &mut args.epg_auto_match),
+            ("html_report", self.html_report, &mut args.html_report),
+            ("feed", self.feed, &mut args.feed),
+            ("notify_always", self.notify_always, &mut args.notify_always),
+        ];
+        for (id, value, arg) in flags {
+            set(matches, id, value, arg);
+        }
+
+        set(matches, "concurrency", self.concurrency.map(|v| v.max(1)), &mut args.concurrency);
+        set(matches, "stale", self.stale, &mut args.stale);
+        set(matches, "diff_dir", self.diff_dir.clone().map(Some), &mut args.diff_dir);
+        set(matches, "diff_format", self.diff_format.map(Some), &mut args.diff_format);
+        set(matches, "format", self.format, &mut args.format);
+        set(matches, "epg_past_days", self.epg_past_days.map(Some), &mut args.epg_past_days);
+        set(matches, "epg_future_days", self.epg_future_days.map(Some), &mut args.epg_future_days);
+        set(matches, "epg_timezone", self.epg_timezone.clone().map(Some), &mut args.epg_timezone);
+        set(matches, "epg_source", self.epg_source.clone(), &mut args.epg_source);
+        set(matches, "epg_map", self.epg_map.clone().map(Some), &mut args.epg_map);
+        set(matches, "epg_match_threshold", self.epg_match_threshold, &mut args.epg_match_threshold);
+        set(matches, "backups", self.backups, &mut args.backups);
+        set(matches, "max_deleted", self.max_deleted, &mut args.max_deleted);
+        set(matches, "feed_entries", self.feed_entries, &mut args.feed_entries);
+        set(matches, "keep_diff_runs", self.keep_diff_runs, &mut args.keep_diff_runs);
+        set(matches, "max_diff_age", self.max_diff_age, &mut args.max_diff_age);
+        set(matches, "webhook", self.webhook.clone(), &mut args.webhook);
+        set(matches, "ntfy", self.ntfy.clone(), &mut args.ntfy);
+        set(matches, "gotify", self.gotify.clone(), &mut args.gotify);
+        set(matches, "smtp_host", self.smtp_host.clone().map(Some), &mut args.smtp_host);
+        set(matches, "smtp_port", self.smtp_port.map(Some), &mut args.smtp_port);
+        set(matches, "smtp_security", self.smtp_security, &mut args.smtp_security);
+        set(matches, "smtp_user", self.smtp_user.clone().map(Some), &mut args.smtp_user);
+        set(matches, "smtp_password", self.smtp_password.clone().map(Some), &mut args.smtp_password);
+        set(matches, "smtp_from", self.smtp_from.clone().map(Some), &mut args.smtp_from);
+        set(matches, "smtp_to", self.smtp_to.clone(), &mut args.smtp_to);
+        set(matches, "warn_days", self.warn_days.map(Some), &mut args.warn_days);
+        set(matches, "timeout", self.timeout, &mut args.timeout);
+        set(matches, "retries", self.retries, &mut args.retries);
+        set(matches, "backoff", self.backoff, &mut args.backoff);
+        set(matches, "max_backoff", self.max_backoff, &mut args.max_backoff);
+    }
+}
+
+// Load the config file and apply the selected profile to args. A missing default config
+// file is not an error, but a missing --config file or --profile is.
+pub fn resolve(args: &mut Args, matches: &ArgMatches) -> Result<(), String> {
+    let path = match &args.config {
+        Some(p) => Some(PathBuf::from(p)),
+        None => default_path().filter(|p| p.exists()),
+    };
+    let config = match path {
+        Some(p) => Config::load(&p)?,
+        None => Config::default(),
+    };
+    let profile_name = args.profile.clone().or(config.default_profile.clone());
+    if let Some(name) = profile_name {
+        let profile = config.profile(&name)?.clone();
+        profile.apply(args, matches);
+    }
+
+    for (value, flag, env) in [
+        (&args.server, "-s/--server", "XTREAM_SERVER"),
+        (&args.username, "-u/--username", "XTREAM_USERNAME"),
+        (&args.password, "-p/--password", "XTREAM_PASSWORD"),
+    ] {
+        if value.is_empty() {
+            return Err(format!("Missing {flag}: set it on the command line, with {env} or in a profile"));
+        }
+    }
+    Ok(())
+}
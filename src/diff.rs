@@ -0,0 +1,855 @@
+use serde::{Deserialize, Serialize};
+use static_str_ops::static_format;
+use std::collections::{BTreeMap, HashSet};
+use std::fmt::Write;
+use std::fs::{create_dir_all, read_dir, read_to_string, rename};
+use std::path::{Path, PathBuf};
+
+use crate::atomic::write_atomic;
+use crate::fetch::Group;
+use crate::types::Channel;
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DiffFormat {
+    Json,
+    Ndjson,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeKind {
+    Added,
+    Removed,
+    Renamed,
+    Moved,
+    Metadata,
+}
+
+// One change to a stream. channel is the stream as it is now, or as it was for a removed
+// stream, and old is the previous version of a changed stream.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Change {
+    pub kind: ChangeKind,
+    pub channel: Channel,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub old: Option<Channel>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub fields: Vec<String>,
+}
+
+impl Change {
+    fn new(kind: ChangeKind, channel: &Channel, old: Option<&Channel>) -> Change {
+        Change {
+            kind,
+            channel: channel.clone(),
+            old: old.cloned(),
+            fields: vec![],
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        let name = &self.channel.name;
+        let old = self.old.as_ref().unwrap_or(&self.channel);
+        match self.kind {
+            ChangeKind::Added => format!("+ {name}"),
+            ChangeKind::Removed => format!("- {name}"),
+            ChangeKind::Renamed => format!("~ {} -> {name}", old.name),
+            ChangeKind::Moved => format!("> {name}: {} -> {}", old.category, self.channel.category),
+            ChangeKind::Metadata => format!("* {name}: {} changed", self.fields.join(", ")),
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Default, Clone, Copy)]
+pub struct ChangeCounts {
+    pub added: u32,
+    pub removed: u32,
+    pub renamed: u32,
+    pub moved: u32,
+    pub metadata: u32,
+}
+
+impl ChangeCounts {
+    pub fn of<'a>(changes: impl IntoIterator<Item = &'a Change>) -> ChangeCounts {
+        let mut counts = ChangeCounts::default();
+        for c in changes {
+            match c.kind {
+                ChangeKind::Added => counts.added += 1,
+                ChangeKind::Removed => counts.removed += 1,
+                ChangeKind::Renamed => counts.renamed += 1,
+                ChangeKind::Moved => counts.moved += 1,
+                ChangeKind::Metadata => counts.metadata += 1,
+            }
+        }
+        counts
+    }
+
+    pub fn add(&mut self, other: &ChangeCounts) {
+        self.added += other.added;
+        self.removed += other.removed;
+        self.renamed += other.renamed;
+        self.moved += other.moved;
+        self.metadata += other.metadata;
+    }
+
+    pub fn total(&self) -> u32 {
+        self.added + self.removed + self.renamed + self.moved + self.metadata
+    }
+}
+
+// Every stream of a type as of the last accepted run
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Snapshot {
+    pub channels: Vec<Channel>,
+}
+
+const SNAPSHOT: &str = "snapshot.json";
+
+impl Snapshot {
+    // Older versions only saved channel names, in snapshot.txt or one {group}_all.txt per
+    // group. Those are read without stream ids, and compared by name instead.
+    pub fn load(dir: &Path) -> Option<Snapshot> {
+        if let Ok(s) = read_to_string(dir.join(SNAPSHOT)) {
+            return serde_json::from_str(&s).ok();
+        }
+        if let Ok(s) = read_to_string(dir.join("snapshot.txt")) {
+            return Some(Snapshot::from_names(&s, ""));
+        }
+        let mut legacy: Vec<(String, PathBuf)> = read_dir(dir)
+            .ok()?
+            .flatten()
+            .filter_map(|e| {
+                let name = e.file_name().to_string_lossy().to_string();
+                let group = name.strip_suffix("_all.txt")?.to_string();
+                Some((group, e.path()))
+            })
+            .collect();
+        if legacy.is_empty() {
+            return None;
+        }
+        legacy.sort();
+        let mut snapshot = Snapshot::default();
+        for (group, path) in legacy {
+            let names = read_to_string(path).unwrap_or_default();
+            snapshot
+                .channels
+                .extend(Snapshot::from_names(&names, &group).channels);
+        }
+        Some(snapshot)
+    }
+
+    fn from_names(names: &str, category: &str) -> Snapshot {
+        let channels = names
+            .lines()
+            .filter(|l| !l.is_empty() && !l.starts_with('#'))
+            .map(|name| Channel {
+                stream_id: "".to_string(),
+                name: name.to_string(),
+                category: category.to_string(),
+                epg_id: "".to_string(),
+                logo: "".to_string(),
+                ext: "".to_string(),
+                added: "".to_string(),
+                episode: None,
+            })
+            .collect();
+        Snapshot { channels }
+    }
+
+    pub fn save(&self, name: &Path) -> std::io::Result<()> {
+        if let Some(dir) = name.parent() {
+            create_dir_all(dir)?;
+        }
+        let contents = serde_json::to_string(self).map_err(std::io::Error::other)?;
+        write_atomic(name, &contents, 0)
+    }
+
+    pub fn path(dir: &Path) -> PathBuf {
+        dir.join(SNAPSHOT)
+    }
+
+    fn has_ids(&self) -> bool {
+        self.channels.iter().all(|c| !c.stream_id.is_empty())
+    }
+
+    // The channels of this run, plus the previous channels of categories that could not
+    // be fully fetched, so a failed request does not look like removed streams
+    pub fn from_groups(groups: &[Group], previous: Option<&Snapshot>) -> Snapshot {
+        let mut channels: Vec<Channel> =
+            groups.iter().flat_map(|g| g.channels()).cloned().collect();
+        if let Some(previous) = previous {
+            let ids = previous.has_ids();
+            let present: HashSet<String> = channels.iter().map(|c| key(c, ids)).collect();
+            for g in groups.iter().filter(|g| !g.complete) {
+                channels.extend(
+                    previous
+                        .channels
+                        .iter()
+                        .filter(|c| c.category == g.category.category_name)
+                        .filter(|c| !present.contains(&key(c, ids)))
+                        .cloned(),
+                );
+            }
+        }
+        Snapshot { channels }
+    }
+
+    // How many of the previous streams are missing from current
+    pub fn vanished(&self, current: &Snapshot) -> usize {
+        let ids = self.has_ids();
+        let present: HashSet<String> = current.channels.iter().map(|c| key(c, ids)).collect();
+        let previous: HashSet<String> = self.channels.iter().map(|c| key(c, ids)).collect();
+        previous.iter().filter(|k| !present.contains(*k)).count()
+    }
+
+    pub fn len(&self) -> usize {
+        self.channels.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.channels.is_empty()
+    }
+}
+
+fn key(c: &Channel, ids: bool) -> String {
+    match ids {
+        true => c.stream_id.clone(),
+        false => c.name.clone(),
+    }
+}
+
+fn metadata_changes(old: &Channel, new: &Channel) -> Vec<String> {
+    let mut fields = vec![];
+    if old.epg_id != new.epg_id {
+        fields.push("epg_id".to_string());
+    }
+    if old.logo != new.logo {
+        fields.push("logo".to_string());
+    }
+    if old.ext != new.ext {
+        fields.push("ext".to_string());
+    }
+    fields
+}
+
+// Changes between two versions of the same stream
+fn compare_pair(old: &Channel, new: &Channel, ids: bool, changes: &mut Vec<Change>) {
+    // Legacy snapshots are matched by name and have no categories or metadata to compare
+    if !ids {
+        return;
+    }
+    if old.name != new.name {
+        changes.push(Change::new(ChangeKind::Renamed, new, Some(old)));
+    }
+    if old.category != new.category {
+        changes.push(Change::new(ChangeKind::Moved, new, Some(old)));
+    }
+    let fields = metadata_changes(old, new);
+    if !fields.is_empty() {
+        let mut change = Change::new(ChangeKind::Metadata, new, Some(old));
+        change.fields = fields;
+        changes.push(change);
+    }
+}
+
+// Compare streams by stream_id. The same stream can be listed in more than one category,
+// so streams are first paired within the same category and only the rest count as moved.
+pub fn compare(previous: &Snapshot, current: &Snapshot) -> Vec<Change> {
+    let ids = previous.has_ids();
+    let mut by_key: BTreeMap<String, (Vec<&Channel>, Vec<&Channel>)> = BTreeMap::new();
+    for c in &previous.channels {
+        by_key.entry(key(c, ids)).or_default().0.push(c);
+    }
+    for c in &current.channels {
+        by_key.entry(key(c, ids)).or_default().1.push(c);
+    }
+
+    let mut changes = vec![];
+    for (_, (mut old, mut new)) in by_key {
+        let mut i = 0;
+        while i < old.len() {
+            match new.iter().position(|n| n.category == old[i].category) {
+                Some(j) => compare_pair(old.remove(i), new.remove(j), ids, &mut changes),
+                None => i += 1,
+            }
+        }
+        let paired = old.len().min(new.len());
+        for (o, n) in old.iter().zip(new.iter()) {
+            compare_pair(o, n, ids, &mut changes);
+        }
+        for n in &new[paired..] {
+            changes.push(Change::new(ChangeKind::Added, n, None));
+        }
+        for o in &old[paired..] {
+            changes.push(Change::new(ChangeKind::Removed, o, None));
+        }
+    }
+    changes
+}
+
+// Write the changes to one diff file per category, or a single one for all categories
+pub fn write_diff_files(
+    dir: &Path,
+    changes: &[Change],
+    shows: Option<&SeriesReport>,
+    single: bool,
+    now: &str,
+) -> std::io::Result<Vec<PathBuf>> {
+    let mut by_group: BTreeMap<&str, Vec<&Change>> = BTreeMap::new();
+    for c in changes {
+        let group = match single {
+            true => "ALL",
+            false => c.channel.category.as_str(),
+        };
+        by_group.entry(group).or_default().push(c);
+    }
+
+    create_dir_all(dir)?;
+    let mut names = vec![];
+    for (group, mut group_changes) in by_group {
+        group_changes.sort_by(|a, b| (a.kind, &a.channel.name).cmp(&(b.kind, &b.channel.name)));
+        let mut contents = String::new();
+        if let Some(shows) = shows {
+            for line in shows.lines((!single).then_some(group)) {
+                let _ = writeln!(contents, "{line}");
+            }
+        }
+        for c in &group_changes {
+            let _ = writeln!(contents, "{}", c.describe());
+        }
+        let name = dir.join(sanitise_file_name::sanitise(static_format!(
+            "{group}_diff_{now}.txt"
+        )));
+        write_atomic(&name, &contents, 0)?;
+        let counts = ChangeCounts::of(group_changes.iter().copied());
+        println!(
+            "Added {}, Deleted {}, Renamed {}, Moved {}, Changed {}, Total {} saved to {name:?}",
+            counts.added,
+            counts.removed,
+            counts.renamed,
+            counts.moved,
+            counts.metadata,
+            counts.total()
+        );
+        names.push(name);
+    }
+    Ok(names)
+}
+
+// A show that appeared or disappeared
+#[derive(Serialize, Debug, Clone)]
+pub struct ShowChange {
+    pub series_id: String,
+    pub name: String,
+    pub category: String,
+    pub episodes: usize,
+}
+
+// Episodes added to or removed from one season of a show that is still there
+#[derive(Serialize, Debug, Clone)]
+pub struct SeasonChange {
+    pub series_id: String,
+    pub series: String,
+    pub category: String,
+    pub season: String,
+    pub added: u32,
+    pub removed: u32,
+}
+
+// Series changes by show and season, on top of the per-episode changes
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct SeriesReport {
+    pub added: Vec<ShowChange>,
+    pub removed: Vec<ShowChange>,
+    pub seasons: Vec<SeasonChange>,
+}
+
+fn count_shows(snapshot: &Snapshot) -> BTreeMap<&str, (&Channel, usize)> {
+    let mut shows: BTreeMap<&str, (&Channel, usize)> = BTreeMap::new();
+    for c in &snapshot.channels {
+        if let Some(e) = &c.episode {
+            shows.entry(e.series_id.as_str()).or_insert((c, 0)).1 += 1;
+        }
+    }
+    shows
+}
+
+fn show_change(id: &str, c: &Channel, episodes: usize) -> ShowChange {
+    ShowChange {
+        series_id: id.to_string(),
+        name: c.group_title().to_string(),
+        category: c.category.clone(),
+        episodes,
+    }
+}
+
+fn plural_episodes(n: u32) -> String {
+    match n {
+        1 => "1 episode".to_string(),
+        _ => format!("{n} episodes"),
+    }
+}
+
+impl SeriesReport {
+    // Shows are matched by series_id and episodes by their stream id. Legacy snapshots
+    // have neither, so they give no report.
+    pub fn new(previous: &Snapshot, current: &Snapshot, changes: &[Change]) -> SeriesReport {
+        let mut report = SeriesReport::default();
+        if !previous.has_ids() {
+            return report;
+        }
+        let (old, new) = (count_shows(previous), count_shows(current));
+        for (id, (c, n)) in &new {
+            if !old.contains_key(id) {
+                report.added.push(show_change(id, c, *n));
+            }
+        }
+        for (id, (c, n)) in &old {
+            if !new.contains_key(id) {
+                report.removed.push(show_change(id, c, *n));
+            }
+        }
+
+        let mut seasons: BTreeMap<(&str, &str), SeasonChange> = BTreeMap::new();
+        for c in changes {
+            let Some(e) = &c.channel.episode else {
+                continue;
+            };
+            if !(old.contains_key(e.series_id.as_str()) && new.contains_key(e.series_id.as_str())) {
+                continue;
+            }
+            let season = seasons
+                .entry((&e.series_id, &e.season))
+                .or_insert_with(|| SeasonChange {
+                    series_id: e.series_id.clone(),
+                    series: e.series_name.clone(),
+                    category: c.channel.category.clone(),
+                    season: e.season.clone(),
+                    added: 0,
+                    removed: 0,
+                });
+            match c.kind {
+                ChangeKind::Added => season.added += 1,
+                ChangeKind::Removed => season.removed += 1,
+                _ => (),
+            }
+        }
+        report.seasons = seasons
+            .into_values()
+            .filter(|s| s.added + s.removed > 0)
+            .collect();
+        report.seasons.sort_by(|a, b| {
+            let season = |s: &SeasonChange| s.season.parse::<u32>().unwrap_or(u32::MAX);
+            (&a.series, season(a), &a.season).cmp(&(&b.series, season(b), &b.season))
+        });
+        report.added.sort_by(|a, b| a.name.cmp(&b.name));
+        report.removed.sort_by(|a, b| a.name.cmp(&b.name));
+        report
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.seasons.is_empty()
+    }
+
+    // One line per show or season, for a single category or all of them
+    pub fn lines(&self, category: Option<&str>) -> Vec<String> {
+        let in_category = |c: &str| category.is_none_or(|cat| cat == c);
+        let mut lines = vec![];
+        for s in self.added.iter().filter(|s| in_category(&s.category)) {
+            lines.push(format!(
+                "+ {} (new show, {})",
+                s.name,
+                plural_episodes(s.episodes as u32)
+            ));
+        }
+        for s in self.removed.iter().filter(|s| in_category(&s.category)) {
+            lines.push(format!(
+                "- {} (removed show, {})",
+                s.name,
+                plural_episodes(s.episodes as u32)
+            ));
+        }
+        for s in self.seasons.iter().filter(|s| in_category(&s.category)) {
+            let season = match s.season.is_empty() {
+                true => "".to_string(),
+                false => format!(" in Season {}", s.season),
+            };
+            if s.added > 0 {
+                lines.push(format!(
+                    "{}: +{}{season}",
+                    s.series,
+                    plural_episodes(s.added)
+                ));
+            }
+            if s.removed > 0 {
+                lines.push(format!(
+                    "{}: -{}{season}",
+                    s.series,
+                    plural_episodes(s.removed)
+                ));
+            }
+        }
+        lines
+    }
+}
+
+// The run and type a set of changes belongs to
+#[derive(Serialize, Debug, Clone)]
+pub struct RunInfo {
+    pub timestamp: String,
+    pub provider: String,
+    #[serde(rename = "type")]
+    pub group_type: String,
+}
+
+// A change flattened for the JSON and NDJSON diff files
+#[derive(Serialize, Debug)]
+struct ChangeRecord<'a> {
+    kind: ChangeKind,
+    category: &'a str,
+    stream_id: &'a str,
+    name: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    series: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    season: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    old_name: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    old_category: Option<&'a str>,
+    #[serde(skip_serializing_if = "<[String]>::is_empty")]
+    fields: &'a [String],
+}
+
+impl<'a> From<&'a Change> for ChangeRecord<'a> {
+    fn from(c: &'a Change) -> ChangeRecord<'a> {
+        let channel = &c.channel;
+        let old = c.old.as_ref();
+        ChangeRecord {
+            kind: c.kind,
+            category: &channel.category,
+            stream_id: &channel.stream_id,
+            name: &channel.name,
+            series: channel.episode.as_ref().map(|e| e.series_name.as_str()),
+            season: channel.episode.as_ref().map(|e| e.season.as_str()),
+            old_name: old
+                .filter(|o| o.name != channel.name)
+                .map(|o| o.name.as_str()),
+            old_category: old
+                .filter(|o| o.category != channel.category)
+                .map(|o| o.category.as_str()),
+            fields: &c.fields,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct JsonDiff<'a> {
+    #[serde(flatten)]
+    run: &'a RunInfo,
+    summary: ChangeCounts,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    shows: Option<&'a SeriesReport>,
+    changes: Vec<ChangeRecord<'a>>,
+}
+
+#[derive(Serialize)]
+struct NdjsonLine<'a> {
+    #[serde(flatten)]
+    run: &'a RunInfo,
+    #[serde(flatten)]
+    change: ChangeRecord<'a>,
+}
+
+// Write all changes of a type to {type}_diff_{now}.json, or one change per line to .ndjson
+pub fn write_structured_diff(
+    dir: &Path,
+    changes: &[Change],
+    shows: Option<&SeriesReport>,
+    format: DiffFormat,
+    run: &RunInfo,
+    now: &str,
+) -> std::io::Result<PathBuf> {
+    create_dir_all(dir)?;
+    let (contents, ext) = match format {
+        DiffFormat::Json => {
+            let diff = JsonDiff {
+                run,
+                summary: ChangeCounts::of(changes),
+                shows,
+                changes: changes.iter().map(ChangeRecord::from).collect(),
+            };
+            let contents = serde_json::to_string_pretty(&diff).map_err(std::io::Error::other)?;
+            (contents, "json")
+        }
+        DiffFormat::Ndjson => {
+            let mut contents = String::new();
+            for c in changes {
+                let line = NdjsonLine {
+                    run,
+                    change: c.into(),
+                };
+                let line = serde_json::to_string(&line).map_err(std::io::Error::other)?;
+                let _ = writeln!(contents, "{line}");
+            }
+            (contents, "ndjson")
+        }
+    };
+    let name = dir.join(static_format!("{}_diff_{now}.{ext}", run.group_type));
+    write_atomic(&name, &contents, 0)?;
+    Ok(name)
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct TypeSummary {
+    pub streams: usize,
+    // Streams with no known category, in the uncategorized group
+    pub uncategorized: usize,
+    #[serde(flatten)]
+    pub changes: ChangeCounts,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shows: Option<SeriesReport>,
+    pub files: Vec<PathBuf>,
+}
+
+// Counts of every type in a run, saved as run_summary.json in the output directory
+#[derive(Serialize, Debug, Default)]
+pub struct RunSummary {
+    pub timestamp: String,
+    pub provider: String,
+    pub types: BTreeMap<String, TypeSummary>,
+    pub total: ChangeCounts,
+    pub failures: Vec<String>,
+    pub rejected: Vec<String>,
+}
+
+impl RunSummary {
+    pub fn save(&self, dir: &Path) -> std::io::Result<PathBuf> {
+        create_dir_all(dir)?;
+        let name = dir.join("run_summary.json");
+        let contents = serde_json::to_string_pretty(self).map_err(std::io::Error::other)?;
+        write_atomic(&name, &contents, 0)?;
+        Ok(name)
+    }
+}
+
+// Diff files and snapshots that older versions kept in the M3U directory
+fn is_diff_file(name: &str) -> bool {
+    name == SNAPSHOT
+        || name == "snapshot.txt"
+        || name.ends_with("_all.txt")
+        || name.starts_with("rejected_")
+        || (name.contains("_diff_")
+            && [".txt", ".json", ".ndjson"]
+                .iter()
+                .any(|e| name.ends_with(e)))
+}
+
+// Move the diff files of older versions from old to dir. Files that already exist in dir
+// are left where they are.
+pub fn migrate(old: &Path, dir: &Path) -> std::io::Result<Vec<String>> {
+    let mut moved = vec![];
+    if old == dir || !old.is_dir() {
+        return Ok(moved);
+    }
+    let mut names: Vec<String> = read_dir(old)?
+        .flatten()
+        .filter(|e| e.path().is_file())
+        .map(|e| e.file_name().to_string_lossy().to_string())
+        .filter(|n| is_diff_file(n))
+        .collect();
+    names.sort();
+    for name in names {
+        let dest = dir.join(&name);
+        if dest.exists() {
+            continue;
+        }
+        create_dir_all(dir)?;
+        rename(old.join(&name), dest)?;
+        moved.push(name);
+    }
+    Ok(moved)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::Category;
+
+    fn channel(id: &str, name: &str, category: &str) -> Channel {
+        Channel {
+            stream_id: id.to_string(),
+            name: name.to_string(),
+            category: category.to_string(),
+            epg_id: "".to_string(),
+            logo: "".to_string(),
+            ext: "".to_string(),
+            added: "".to_string(),
+            episode: None,
+        }
+    }
+
+    fn snapshot(channels: &[Channel]) -> Snapshot {
+        Snapshot {
+            channels: channels.to_vec(),
+        }
+    }
+
+    fn group(name: &str, channels: &[Channel], complete: bool) -> Group {
+        Group {
+            category: Category {
+                category_id: name.to_lowercase(),
+                category_name: name.to_string(),
+                parent_id: None,
+            },
+            channels: complete.then(|| channels.to_vec()),
+            complete,
+        }
+    }
+
+    fn kinds(changes: &[Change]) -> Vec<(ChangeKind, &str)> {
+        let mut kinds: Vec<_> = changes
+            .iter()
+            .map(|c| (c.kind, c.channel.stream_id.as_str()))
+            .collect();
+        kinds.sort();
+        kinds
+    }
+
+    #[test]
+    fn unchanged() {
+        let channels = [channel("1", "One", "News"), channel("2", "Two", "News")];
+        assert!(compare(&snapshot(&channels), &snapshot(&channels)).is_empty());
+    }
+
+    #[test]
+    fn added_and_removed() {
+        let previous = snapshot(&[channel("1", "One", "News"), channel("2", "Two", "News")]);
+        let current = snapshot(&[channel("1", "One", "News"), channel("3", "Three", "News")]);
+        let changes = compare(&previous, &current);
+        assert_eq!(
+            kinds(&changes),
+            [(ChangeKind::Added, "3"), (ChangeKind::Removed, "2")]
+        );
+    }
+
+    #[test]
+    fn renamed() {
+        let previous = snapshot(&[channel("1", "One", "News")]);
+        let current = snapshot(&[channel("1", "One HD", "News")]);
+        let changes = compare(&previous, &current);
+        assert_eq!(kinds(&changes), [(ChangeKind::Renamed, "1")]);
+        assert_eq!(changes[0].describe(), "~ One -> One HD");
+    }
+
+    #[test]
+    fn moved() {
+        let previous = snapshot(&[channel("1", "One", "News")]);
+        let current = snapshot(&[channel("1", "One", "Sports")]);
+        let changes = compare(&previous, &current);
+        assert_eq!(kinds(&changes), [(ChangeKind::Moved, "1")]);
+        assert_eq!(changes[0].describe(), "> One: News -> Sports");
+    }
+
+    #[test]
+    fn metadata() {
+        let previous = snapshot(&[channel("1", "One", "News")]);
+        let mut new = channel("1", "One", "News");
+        new.epg_id = "one.uk".to_string();
+        new.logo = "http://x/one.png".to_string();
+        let changes = compare(&previous, &snapshot(&[new]));
+        assert_eq!(kinds(&changes), [(ChangeKind::Metadata, "1")]);
+        assert_eq!(changes[0].fields, ["epg_id", "logo"]);
+    }
+
+    #[test]
+    fn same_stream_in_two_categories() {
+        let previous = snapshot(&[channel("1", "One", "News"), channel("1", "One", "UK")]);
+        // Listed in the same categories in another order: nothing changed
+        let current = snapshot(&[channel("1", "One", "UK"), channel("1", "One", "News")]);
+        assert!(compare(&previous, &current).is_empty());
+
+        // Only the copy that left UK moved
+        let current = snapshot(&[channel("1", "One", "News"), channel("1", "One", "Sports")]);
+        let changes = compare(&previous, &current);
+        assert_eq!(kinds(&changes), [(ChangeKind::Moved, "1")]);
+        assert_eq!(changes[0].old.as_ref().unwrap().category, "UK");
+        assert_eq!(changes[0].channel.category, "Sports");
+
+        // Dropped from one category only
+        let current = snapshot(&[channel("1", "One", "News")]);
+        let changes = compare(&previous, &current);
+        assert_eq!(kinds(&changes), [(ChangeKind::Removed, "1")]);
+        assert_eq!(changes[0].channel.category, "UK");
+    }
+
+    #[test]
+    fn duplicate_names() {
+        // Different streams with the same name are told apart by their ids
+        let previous = snapshot(&[channel("1", "News", "UK"), channel("2", "News", "UK")]);
+        let current = snapshot(&[channel("2", "News", "UK")]);
+        let changes = compare(&previous, &current);
+        assert_eq!(kinds(&changes), [(ChangeKind::Removed, "1")]);
+    }
+
+    #[test]
+    fn failed_categories_are_carried_forward() {
+        let previous = snapshot(&[
+            channel("1", "One", "News"),
+            channel("2", "Two", "Sports"),
+            channel("3", "Three", "Sports"),
+        ]);
+        let groups = [
+            group("News", &[channel("1", "One", "News")], true),
+            group("Sports", &[], false),
+        ];
+        let current = Snapshot::from_groups(&groups, Some(&previous));
+        assert_eq!(current.len(), 3);
+        assert!(compare(&previous, &current).is_empty());
+        assert_eq!(previous.vanished(&current), 0);
+
+        // A complete category that lost a stream is not carried forward
+        let groups = [group("News", &[], true), group("Sports", &[], false)];
+        let current = Snapshot::from_groups(&groups, Some(&previous));
+        let changes = compare(&previous, &current);
+        assert_eq!(kinds(&changes), [(ChangeKind::Removed, "1")]);
+    }
+
+    #[test]
+    fn partly_fetched_category_keeps_missing_streams() {
+        let previous = snapshot(&[channel("1", "One", "News"), channel("2", "Two", "News")]);
+        let mut partial = group("News", &[channel("1", "One HD", "News")], true);
+        partial.complete = false;
+        let current = Snapshot::from_groups(&[partial], Some(&previous));
+        let changes = compare(&previous, &current);
+        assert_eq!(kinds(&changes), [(ChangeKind::Renamed, "1")]);
+    }
+
+    #[test]
+    fn legacy_snapshot_by_name() {
+        let previous = Snapshot::from_names("# saved by an old version\nOne\nTwo\nTwo\n", "News");
+        assert!(!previous.has_ids());
+        let current = snapshot(&[
+            channel("1", "One", "Sports"),
+            channel("2", "Two", "News"),
+            channel("3", "Three", "News"),
+        ]);
+        // Matched by name only, so the move of One is not seen and one of the two Twos is gone
+        let changes = compare(&previous, &current);
+        assert_eq!(
+            kinds(&changes),
+            [(ChangeKind::Added, "3"), (ChangeKind::Removed, "")]
+        );
+        assert_eq!(
+            changes
+                .iter()
+                .find(|c| c.kind == ChangeKind::Removed)
+                .unwrap()
+                .channel
+                .name,
+            "Two"
+        );
+        assert_eq!(previous.vanished(&current), 0);
+    }
+}
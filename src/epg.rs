@@ -0,0 +1,634 @@
+use chrono::{DateTime, FixedOffset, NaiveDateTime, Offset, TimeDelta, TimeZone, Utc};
+use chrono_tz::Tz;
+use flate2::Compression;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use quick_xml::events::{BytesStart, BytesText, Event};
+use quick_xml::{Reader, Writer};
+use std::collections::{HashMap, HashSet};
+use std::fs::{create_dir_all, read, read_to_string};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use crate::atomic::AtomicFile;
+use crate::fetch::Group;
+
+const TIME: &str = "%Y%m%d%H%M%S %z";
+
+// External channel ids and the provider stream ids they belong to, from --epg-map
+#[derive(Debug, Default)]
+pub struct EpgMap {
+    pub streams: HashMap<String, String>,
+}
+
+impl EpgMap {
+    // A TOML table of "external id" = stream id
+    pub fn load(path: &Path) -> Result<EpgMap, String> {
+        let contents = read_to_string(path).map_err(|e| format!("Error reading {path:?}: {e}"))?;
+        let table: toml::Table =
+            toml::from_str(&contents).map_err(|e| format!("Error in {path:?}: {e}"))?;
+        let mut streams = HashMap::new();
+        for (id, value) in table {
+            let stream_id = match value {
+                toml::Value::String(s) => s,
+                toml::Value::Integer(i) => i.to_string(),
+                v => return Err(format!("Error in {path:?}: {id} = {v} is not a stream id")),
+            };
+            streams.insert(id, stream_id);
+        }
+        Ok(EpgMap { streams })
+    }
+
+    // Mapped channels without an EPG id of their own get the external id, so the M3U has a
+    // tvg-id to find them in the guide
+    pub fn apply(&self, groups: &mut [Group]) {
+        let ids: HashMap<&str, &str> = self
+            .streams
+            .iter()
+            .map(|(id, stream_id)| (stream_id.as_str(), id.as_str()))
+            .collect();
+        for channel in groups
+            .iter_mut()
+            .flat_map(|g| g.channels.iter_mut().flatten())
+        {
+            if channel.epg_id.is_empty()
+                && let Some(id) = ids.get(channel.stream_id.as_str())
+            {
+                channel.epg_id = id.to_string();
+            }
+        }
+    }
+
+    // The tvg-id each external id ends up as, and the stream ids that are not in the
+    // playlists
+    pub fn resolve(&self, groups: &[Group]) -> (HashMap<String, String>, Vec<String>) {
+        let epg_ids: HashMap<&str, &str> = groups
+            .iter()
+            .flat_map(|g| g.channels())
+            .filter(|c| !c.epg_id.is_empty())
+            .map(|c| (c.stream_id.as_str(), c.epg_id.as_str()))
+            .collect();
+        let mut ids = HashMap::new();
+        let mut unknown = vec![];
+        for (id, stream_id) in &self.streams {
+            match epg_ids.get(stream_id.as_str()) {
+                Some(epg_id) => {
+                    ids.insert(id.clone(), epg_id.to_string());
+                }
+                None => unknown.push(stream_id.clone()),
+            }
+        }
+        unknown.sort();
+        (ids, unknown)
+    }
+}
+
+// The part of an XMLTV guide that belongs to the channels of the playlists. Channels and
+// programmes are kept apart so guides can be merged and still have every <channel> before
+// the first <programme>, as XMLTV requires.
+#[derive(Debug, Default)]
+pub struct Epg {
+    pub channels: usize,
+    pub programmes: usize,
+    // The output ids of the channels that are in the guide
+    pub found: HashSet<String>,
+    head: Vec<u8>,
+    channel_xml: Vec<u8>,
+    programme_xml: Vec<u8>,
+}
+
+// What to keep of a guide and how to change it
+#[derive(Debug, Default, Clone)]
+pub struct Filter {
+    // The ids of the guide to keep and the id each becomes in the output, which is the tvg-id
+    // of the M3U
+    pub channels: HashMap<String, String>,
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    // The timezone the provider really uses for the programme times
+    pub timezone: Option<Tz>,
+}
+
+impl Filter {
+    // Keep the programmes from past_days ago to future_days ahead. timezone is the name of
+    // the timezone the programme times are really in, "server" for server_timezone, which is
+    // server_info.timezone.
+    pub fn new(
+        groups: &[Group],
+        past_days: Option<u32>,
+        future_days: Option<u32>,
+        timezone: Option<&str>,
+        server_timezone: &str,
+    ) -> Result<Filter, String> {
+        let channels = groups
+            .iter()
+            .flat_map(|g| g.channels())
+            .filter(|c| !c.epg_id.is_empty())
+            .map(|c| (c.epg_id.clone(), c.epg_id.clone()))
+            .collect();
+        let now = Utc::now();
+        let days = |d: u32| TimeDelta::days(d as i64);
+        let timezone = match timezone {
+            None => None,
+            Some("server") if server_timezone.is_empty() => {
+                return Err("the server did not send its timezone".to_string());
+            }
+            Some("server") => Some(server_timezone),
+            Some(name) => Some(name),
+        };
+        let timezone = timezone
+            .map(|name| {
+                name.parse::<Tz>()
+                    .map_err(|_| format!("unknown timezone {name}"))
+            })
+            .transpose()?;
+        Ok(Filter {
+            channels,
+            from: past_days.map(|d| now - days(d)),
+            to: future_days.map(|d| now + days(d)),
+            timezone,
+        })
+    }
+
+    // The filter for an external guide: the channels that are not in the guide yet, under
+    // their own id or an id from --epg-map. The provider timezone does not apply to it.
+    pub fn external(&self, map: &HashMap<String, String>, found: &HashSet<String>) -> Filter {
+        let channels = self
+            .channels
+            .values()
+            .map(|id| (id.clone(), id.clone()))
+            .chain(map.iter().map(|(id, out)| (id.clone(), out.clone())))
+            .filter(|(_, out)| !found.contains(out))
+            .collect();
+        Filter {
+            channels,
+            timezone: None,
+            ..self.clone()
+        }
+    }
+
+    // The <channel> tag to write, under its output id, or None when it is not kept
+    fn channel(&self, e: &BytesStart) -> Option<BytesStart<'static>> {
+        let id = attribute(e, b"id")?;
+        let out = self.channels.get(&id)?;
+        Some(with_attributes(e, &[("id", Some(out.clone()))]))
+    }
+
+    // The <programme> tag to write, with its output channel id and corrected times, or None
+    // when it is not kept
+    fn programme(&self, e: &BytesStart) -> Option<BytesStart<'static>> {
+        let id = attribute(e, b"channel")?;
+        let out = self.channels.get(&id)?;
+        let start = attribute(e, b"start").and_then(|t| self.time(&t));
+        let stop = attribute(e, b"stop").and_then(|t| self.time(&t));
+        // Programmes without a stop time end when they start, for the window
+        let end = stop.or(start);
+        if let (Some(from), Some(end)) = (self.from, end)
+            && end < from
+        {
+            return None;
+        }
+        if let (Some(to), Some(start)) = (self.to, start)
+            && start > to
+        {
+            return None;
+        }
+        let format = |t: Option<DateTime<FixedOffset>>| match self.timezone {
+            Some(_) => t.map(|t| t.format(TIME).to_string()),
+            None => None,
+        };
+        Some(with_attributes(
+            e,
+            &[
+                ("channel", Some(out.clone())),
+                ("start", format(start)),
+                ("stop", format(stop)),
+            ],
+        ))
+    }
+
+    // Parse an XMLTV time. With a timezone the offset in the guide is ignored and the time
+    // is taken as local time in that timezone.
+    fn time(&self, s: &str) -> Option<DateTime<FixedOffset>> {
+        let s = s.trim();
+        let digits = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+        let (local, offset) = s.split_at(digits);
+        // Seconds, and even minutes, are optional
+        let local = format!("{local:0<14}");
+        let local = NaiveDateTime::parse_from_str(local.get(..14)?, "%Y%m%d%H%M%S").ok()?;
+        if let Some(tz) = self.timezone {
+            // A time in the hour skipped when the clocks go forward is taken with the offset
+            // from before the change
+            let t = match tz.from_local_datetime(&local).earliest() {
+                Some(t) => t,
+                None => tz
+                    .offset_from_local_datetime(&(local - TimeDelta::hours(3)))
+                    .earliest()?
+                    .fix()
+                    .from_local_datetime(&local)
+                    .single()?
+                    .with_timezone(&tz),
+            };
+            return Some(t.fixed_offset());
+        }
+        let offset = match offset.trim() {
+            "" => FixedOffset::east_opt(0)?,
+            o => DateTime::parse_from_str(&format!("20000101000000 {o}"), TIME)
+                .ok()?
+                .offset()
+                .to_owned(),
+        };
+        offset.from_local_datetime(&local).single()
+    }
+}
+
+fn attribute(e: &BytesStart, name: &[u8]) -> Option<String> {
+    let attr = e.try_get_attribute(name).ok()??;
+    Some(attr.unescape_value().ok()?.to_string())
+}
+
+// A copy of the tag with the given attributes replaced, None keeps the original value
+fn with_attributes(e: &BytesStart, values: &[(&str, Option<String>)]) -> BytesStart<'static> {
+    let mut tag = BytesStart::new(String::from_utf8_lossy(e.name().as_ref()).into_owned());
+    for attr in e.attributes().flatten() {
+        let key = String::from_utf8_lossy(attr.key.as_ref()).into_owned();
+        match values.iter().find(|(k, _)| *k == key) {
+            Some((_, Some(value))) => tag.push_attribute((key.as_str(), value.as_str())),
+            _ => tag.push_attribute(attr),
+        }
+    }
+    tag
+}
+
+// Where the events of the guide go
+#[derive(Clone, Copy, PartialEq)]
+enum Part {
+    Head,
+    Channels,
+    Programmes,
+    Skip,
+}
+
+impl Epg {
+    // Keep the <channel> and <programme> elements the filter asks for. The guide is
+    // streamed, so the whole document is never held as a tree.
+    pub fn filter(xml: &[u8], filter: &Filter) -> Result<Epg, quick_xml::Error> {
+        let mut epg = Epg::default();
+        let mut reader = Reader::from_reader(xml);
+        let mut head = Writer::new(vec![]);
+        let mut channels = Writer::new(vec![]);
+        let mut programmes = Writer::new(Vec::with_capacity(xml.len() / 4));
+        let mut depth = 0;
+        let mut part = Part::Head;
+        loop {
+            let mut event = reader.read_event()?;
+            // An empty guide can be a single <tv/>, which is opened so channels can be added
+            if let Event::Empty(e) = &event
+                && depth == 0
+                && e.name().as_ref() == b"tv"
+            {
+                event = Event::Start(e.to_owned());
+            }
+            if depth == 1 {
+                let tag = match &event {
+                    Event::Start(e) | Event::Empty(e) => match e.name().as_ref() {
+                        b"channel" => filter.channel(e).map(|t| (Part::Channels, t)),
+                        b"programme" => filter.programme(e).map(|t| (Part::Programmes, t)),
+                        _ => None,
+                    },
+                    _ => None,
+                };
+                match (tag, &event) {
+                    (Some((p, tag)), _) => {
+                        part = p;
+                        match part {
+                            Part::Channels => {
+                                epg.channels += 1;
+                                epg.found.extend(attribute(&tag, b"id"));
+                            }
+                            _ => epg.programmes += 1,
+                        }
+                        event = match event {
+                            Event::Start(_) => Event::Start(tag),
+                            _ => Event::Empty(tag),
+                        };
+                    }
+                    // Anything else inside <tv> is left out, with the whitespace around it
+                    (None, Event::Start(e)) => {
+                        reader.read_to_end(e.name())?;
+                        continue;
+                    }
+                    (None, Event::End(_)) => {
+                        part = Part::Skip;
+                        depth -= 1;
+                        continue;
+                    }
+                    (None, _) => continue,
+                }
+            }
+            let writer = match part {
+                Part::Head => &mut head,
+                Part::Channels => &mut channels,
+                Part::Programmes => &mut programmes,
+                Part::Skip => match event {
+                    Event::Eof => break,
+                    _ => continue,
+                },
+            };
+            if depth == 1 {
+                writer.write_event(Event::Text(BytesText::from_escaped("\n  ")))?;
+            }
+            match &event {
+                Event::Eof => break,
+                Event::Start(_) => depth += 1,
+                Event::End(_) => depth -= 1,
+                _ => (),
+            }
+            writer.write_event(event)?;
+        }
+        epg.head = head.into_inner();
+        epg.channel_xml = channels.into_inner();
+        epg.programme_xml = programmes.into_inner();
+        Ok(epg)
+    }
+
+    // Add the channels and programmes of another guide, which must not have any channel
+    // that is already in this one
+    pub fn merge(&mut self, other: Epg) {
+        self.channels += other.channels;
+        self.programmes += other.programmes;
+        self.found.extend(other.found);
+        self.channel_xml.extend(other.channel_xml);
+        self.programme_xml.extend(other.programme_xml);
+    }
+
+    // The output ids the filter wants that have no <channel> in the guide
+    pub fn missing(&self, filter: &Filter) -> Vec<String> {
+        let mut missing: Vec<String> = filter
+            .channels
+            .values()
+            .filter(|id| !self.found.contains(*id))
+            .cloned()
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect();
+        missing.sort();
+        missing
+    }
+
+    fn to_xml(&self) -> Vec<u8> {
+        let mut xml = self.head.clone();
+        if xml.is_empty() {
+            xml.extend(b"<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<tv>");
+        }
+        xml.extend(&self.channel_xml);
+        xml.extend(&self.programme_xml);
+        xml.extend(b"\n</tv>\n");
+        xml
+    }
+
+    // Write the guide as epg.xml in dir, or gzipped as epg.xml.gz
+    pub fn save(&self, dir: &Path, gzip: bool) -> std::io::Result<PathBuf> {
+        create_dir_all(dir)?;
+        let name = match gzip {
+            false => dir.join("epg.xml"),
+            true => dir.join("epg.xml.gz"),
+        };
+        save(&name, &self.to_xml(), gzip)?;
+        Ok(name)
+    }
+}
+
+// Read a local guide, gunzipped when the name ends in .gz
+pub fn read_source(path: &Path) -> std::io::Result<Vec<u8>> {
+    let data = read(path)?;
+    if path.extension().is_some_and(|e| e == "gz") {
+        let mut xml = vec![];
+        GzDecoder::new(data.as_slice()).read_to_end(&mut xml)?;
+        return Ok(xml);
+    }
+    Ok(data)
+}
+
+fn save(name: &Path, xml: &[u8], gzip: bool) -> std::io::Result<()> {
+    let mut file = AtomicFile::create(name)?;
+    if gzip {
+        let mut gz = GzEncoder::new(file, Compression::default());
+        gz.write_all(xml)?;
+        file = gz.finish()?;
+    } else {
+        file.write_all(xml)?;
+    }
+    file.commit(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GUIDE: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<tv generator-info-name="test">
+  <channel id="bbc1"><display-name>BBC One</display-name></channel>
+  <channel id="other"><display-name>Other</display-name></channel>
+  <programme start="20260701180000 +0000" stop="20260701190000 +0000" channel="bbc1"><title>Early</title></programme>
+  <programme start="20260701200000 +0000" stop="20260701210000 +0000" channel="bbc1"><title>Evening</title></programme>
+  <programme start="20260701200000 +0000" stop="20260701210000 +0000" channel="other"><title>Other</title></programme>
+  <programme start="20260702200000 +0000" channel="bbc1"><title>Tomorrow</title></programme>
+</tv>
+"#;
+
+    fn filter(timezone: Option<&str>) -> Filter {
+        Filter {
+            channels: HashMap::from([("bbc1".to_string(), "bbc1.uk".to_string())]),
+            timezone: timezone.map(|tz| tz.parse().unwrap()),
+            ..Filter::default()
+        }
+    }
+
+    fn utc(s: &str) -> DateTime<Utc> {
+        DateTime::parse_from_str(s, TIME).unwrap().to_utc()
+    }
+
+    fn run(xml: &str, filter: &Filter) -> (Epg, String) {
+        let epg = Epg::filter(xml.as_bytes(), filter).unwrap();
+        let out = String::from_utf8(epg.to_xml()).unwrap();
+        (epg, out)
+    }
+
+    fn time(filter: &Filter, s: &str) -> Option<String> {
+        filter.time(s).map(|t| t.format(TIME).to_string())
+    }
+
+    #[test]
+    fn keeps_only_playlist_channels() {
+        let (epg, out) = run(GUIDE, &filter(None));
+        assert_eq!((epg.channels, epg.programmes), (1, 3));
+        assert!(epg.found.contains("bbc1.uk"));
+        assert!(out.contains(r#"<channel id="bbc1.uk">"#));
+        assert!(!out.contains(r#"id="other""#));
+        assert!(!out.contains("<title>Other</title>"));
+        // Without a timezone the times are left as they are
+        assert!(out.contains(
+            r#"<programme start="20260701200000 +0000" stop="20260701210000 +0000" channel="bbc1.uk">"#
+        ));
+        assert!(out.starts_with("<?xml"));
+        assert!(out.trim_end().ends_with("</tv>"));
+        assert!(epg.missing(&filter(None)).is_empty());
+    }
+
+    #[test]
+    fn window() {
+        let filter = Filter {
+            from: Some(utc("20260701193000 +0000")),
+            to: Some(utc("20260702120000 +0000")),
+            ..filter(None)
+        };
+        let (epg, out) = run(GUIDE, &filter);
+        // Early ended before the window and Tomorrow starts after it
+        assert_eq!(epg.programmes, 1);
+        assert!(out.contains("<title>Evening</title>"));
+        assert!(!out.contains("<title>Early</title>"));
+        assert!(!out.contains("<title>Tomorrow</title>"));
+    }
+
+    #[test]
+    fn window_without_stop() {
+        let filter = Filter {
+            from: Some(utc("20260702193000 +0000")),
+            ..filter(None)
+        };
+        let (epg, out) = run(GUIDE, &filter);
+        assert_eq!(epg.programmes, 1);
+        assert!(out.contains("<title>Tomorrow</title>"));
+    }
+
+    #[test]
+    fn rewrites_wrong_offset() {
+        let (_, out) = run(GUIDE, &filter(Some("Europe/London")));
+        assert!(out.contains(
+            r#"<programme start="20260701200000 +0100" stop="20260701210000 +0100" channel="bbc1.uk">"#
+        ));
+        // A missing stop stays missing
+        assert!(out.contains(r#"<programme start="20260702200000 +0100" channel="bbc1.uk">"#));
+    }
+
+    #[test]
+    fn window_uses_corrected_times() {
+        // Evening really ends at 20:00 UTC, before the window
+        let filter = Filter {
+            from: Some(utc("20260701203000 +0000")),
+            ..filter(Some("Europe/London"))
+        };
+        let (_, out) = run(GUIDE, &filter);
+        assert!(!out.contains("<title>Evening</title>"));
+        assert!(out.contains("<title>Tomorrow</title>"));
+    }
+
+    #[test]
+    fn time_with_offset() {
+        let f = filter(None);
+        assert_eq!(
+            time(&f, "20260701200000 +0200").as_deref(),
+            Some("20260701200000 +0200")
+        );
+        assert_eq!(
+            time(&f, "20260701200000 -0530").as_deref(),
+            Some("20260701200000 -0530")
+        );
+        // No offset is UTC
+        assert_eq!(
+            time(&f, "20260701200000").as_deref(),
+            Some("20260701200000 +0000")
+        );
+        assert_eq!(time(&f, "not a time"), None);
+        assert_eq!(time(&f, "20261301200000 +0000"), None);
+    }
+
+    #[test]
+    fn time_without_seconds_or_minutes() {
+        let f = filter(None);
+        assert_eq!(
+            time(&f, "202607012030 +0000").as_deref(),
+            Some("20260701203000 +0000")
+        );
+        assert_eq!(
+            time(&f, "2026070120 +0100").as_deref(),
+            Some("20260701200000 +0100")
+        );
+    }
+
+    #[test]
+    fn time_in_timezone() {
+        let f = filter(Some("Europe/London"));
+        // The offset in the guide is ignored
+        assert_eq!(
+            time(&f, "20260701200000 +0000").as_deref(),
+            Some("20260701200000 +0100")
+        );
+        assert_eq!(
+            time(&f, "20260115200000 +0500").as_deref(),
+            Some("20260115200000 +0000")
+        );
+        assert_eq!(
+            time(&f, "202607012030").as_deref(),
+            Some("20260701203000 +0100")
+        );
+    }
+
+    #[test]
+    fn time_around_daylight_saving_changes() {
+        let f = filter(Some("Europe/London"));
+        // 01:30 does not exist on the day the clocks go forward
+        assert_eq!(
+            time(&f, "20260329013000 +0000").as_deref(),
+            Some("20260329023000 +0100")
+        );
+        assert_eq!(
+            time(&f, "20260329003000 +0000").as_deref(),
+            Some("20260329003000 +0000")
+        );
+        assert_eq!(
+            time(&f, "20260329020000 +0000").as_deref(),
+            Some("20260329020000 +0100")
+        );
+        // and happens twice when they go back, the first one is used
+        assert_eq!(
+            time(&f, "20261025013000 +0000").as_deref(),
+            Some("20261025013000 +0100")
+        );
+    }
+
+    #[test]
+    fn timezone_names() {
+        let new = |tz, server| Filter::new(&[], None, None, tz, server);
+        assert_eq!(
+            new(Some("server"), "Europe/Berlin").unwrap().timezone,
+            Some(chrono_tz::Europe::Berlin)
+        );
+        assert_eq!(
+            new(Some("America/New_York"), "").unwrap().timezone,
+            Some(chrono_tz::America::New_York)
+        );
+        assert!(new(Some("server"), "").is_err());
+        assert!(new(Some("Mars/Olympus"), "").is_err());
+        assert_eq!(new(None, "Europe/Berlin").unwrap().timezone, None);
+    }
+
+    #[test]
+    fn window_in_days() {
+        let f = Filter::new(&[], Some(1), Some(3), None, "").unwrap();
+        let now = Utc::now();
+        assert!(
+            (now - f.from.unwrap() - TimeDelta::days(1))
+                .num_seconds()
+                .abs()
+                < 60
+        );
+        assert!(
+            (f.to.unwrap() - now - TimeDelta::days(3))
+                .num_seconds()
+                .abs()
+                < 60
+        );
+    }
+}
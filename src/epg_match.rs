@@ -0,0 +1,387 @@
+use quick_xml::Reader;
+use quick_xml::escape::resolve_predefined_entity;
+use quick_xml::events::Event;
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+use std::fs::{create_dir_all, read_to_string};
+use std::path::{Path, PathBuf};
+
+use crate::atomic::write_atomic;
+use crate::fetch::Group;
+
+const MATCHES: &str = "epg_matches.toml";
+
+const HEADER: &str = "\
+# EPG ids found by --epg-auto-match, as \"stream id\" = \"EPG id\". Check them and fix or
+# remove wrong ones. \"\" never matches the stream again. Later runs keep everything here and
+# only add streams that are not in the file yet.
+";
+
+// Words that say something about the stream, not the channel
+const QUALITY: &[&str] = &[
+    "sd", "hd", "fhd", "uhd", "hq", "4k", "8k", "hevc", "h264", "h265", "720p", "1080i", "1080p",
+    "2160p", "50fps", "60fps",
+];
+
+// A <channel> of a guide with its display names
+#[derive(Debug, Clone)]
+pub struct GuideChannel {
+    pub id: String,
+    pub names: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Match {
+    pub stream_id: String,
+    pub name: String,
+    pub epg_id: String,
+    pub display_name: String,
+    pub score: f64,
+}
+
+// The EPG ids chosen for streams without an epg_channel_id, kept in epg_matches.toml next to
+// the guide
+#[derive(Debug, Default)]
+pub struct EpgMatches {
+    path: PathBuf,
+    text: String,
+    pub streams: HashMap<String, String>,
+}
+
+// Lower case words without country prefixes such as "UK:" or "[DE]", punctuation and quality
+// tags, so "UK: BBC One FHD" and "BBC One HD" are both "bbc one"
+pub fn normalize(name: &str) -> String {
+    let mut name = name.trim();
+    for _ in 0..2 {
+        if let Some(rest) = name.strip_prefix(['[', '(']) {
+            name = rest.split_once([']', ')']).map_or(rest, |(_, r)| r);
+        } else if let Some(rest) = name.strip_prefix('|') {
+            name = rest.split_once('|').map_or(rest, |(_, r)| r);
+        } else if let Some((prefix, rest)) = name.split_once([':', '|'])
+            && (1..=4).contains(&prefix.trim().len())
+            && prefix.trim().chars().all(char::is_alphanumeric)
+        {
+            name = rest;
+        }
+        name = name.trim();
+    }
+    name.to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty() && !QUALITY.contains(w))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// The numbers in a normalized name. Channels only match when they are the same, however close
+// the rest of the names are, so "sky sports 1" is never "sky sports 2"
+fn numbers(name: &str) -> Vec<&str> {
+    name.split(|c: char| !c.is_ascii_digit())
+        .filter(|n| !n.is_empty())
+        .collect()
+}
+
+fn last_name(channel: &mut Option<GuideChannel>) -> Option<&mut String> {
+    channel.as_mut()?.names.last_mut()
+}
+
+// The channels of a guide and their <display-name>s
+pub fn guide_channels(xml: &[u8]) -> Result<Vec<GuideChannel>, quick_xml::Error> {
+    let mut channels = vec![];
+    let mut reader = Reader::from_reader(xml);
+    let mut current: Option<GuideChannel> = None;
+    let mut in_name = false;
+    loop {
+        match reader.read_event()? {
+            Event::Eof => break,
+            Event::Start(e) if e.name().as_ref() == b"channel" => {
+                let id = e
+                    .try_get_attribute("id")
+                    .ok()
+                    .flatten()
+                    .and_then(|a| a.unescape_value().ok().map(|v| v.to_string()));
+                current = id.map(|id| GuideChannel { id, names: vec![] });
+            }
+            Event::Start(e) if e.name().as_ref() == b"display-name" => {
+                if let Some(c) = &mut current {
+                    c.names.push(String::new());
+                    in_name = true;
+                }
+            }
+            // Entities like &amp; come between the pieces of text
+            Event::Text(t) if in_name => {
+                if let (Some(name), Ok(text)) = (last_name(&mut current), t.decode()) {
+                    name.push_str(&text);
+                }
+            }
+            Event::GeneralRef(r) if in_name => {
+                let text = match r.resolve_char_ref() {
+                    Ok(Some(c)) => Some(c.to_string()),
+                    _ => r
+                        .decode()
+                        .ok()
+                        .and_then(|n| resolve_predefined_entity(&n).map(str::to_string)),
+                };
+                if let (Some(name), Some(text)) = (last_name(&mut current), text) {
+                    name.push_str(&text);
+                }
+            }
+            Event::End(e) if e.name().as_ref() == b"display-name" => in_name = false,
+            Event::End(e) if e.name().as_ref() == b"channel" => {
+                channels.extend(current.take());
+            }
+            // Programmes come after the channels
+            Event::Start(e) if e.name().as_ref() == b"programme" => break,
+            _ => (),
+        }
+    }
+    Ok(channels)
+}
+
+impl EpgMatches {
+    pub fn load(dir: &Path) -> Result<EpgMatches, String> {
+        let path = dir.join(MATCHES);
+        let text = read_to_string(&path).unwrap_or_default();
+        let streams = toml::from_str::<HashMap<String, String>>(&text)
+            .map_err(|e| format!("Error in {path:?}: {e}"))?;
+        Ok(EpgMatches {
+            path,
+            text,
+            streams,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // Give the streams in the file their EPG id, unless the provider has one for them
+    pub fn apply(&self, groups: &mut [Group]) {
+        for channel in groups
+            .iter_mut()
+            .flat_map(|g| g.channels.iter_mut().flatten())
+        {
+            if channel.epg_id.is_empty()
+                && let Some(id) = self.streams.get(&channel.stream_id)
+            {
+                channel.epg_id = id.clone();
+            }
+        }
+    }
+
+    // Match the streams without an EPG id that are not in the file yet to the channel of the
+    // guides with the most similar name. Matches below the threshold, or too close to the
+    // next best channel, are left for the next run.
+    pub fn find(&self, groups: &[Group], guide: &[GuideChannel], threshold: f64) -> Vec<Match> {
+        let candidates: Vec<(String, &str, &str)> = guide
+            .iter()
+            .flat_map(|c| {
+                c.names
+                    .iter()
+                    .map(move |n| (normalize(n), c.id.as_str(), n.as_str()))
+            })
+            .filter(|(n, _, _)| !n.is_empty())
+            .collect();
+        let candidate_numbers: Vec<Vec<&str>> =
+            candidates.iter().map(|(n, _, _)| numbers(n)).collect();
+        let mut seen = HashSet::new();
+        let mut matches = vec![];
+        for channel in groups.iter().flat_map(|g| g.channels()) {
+            if !channel.epg_id.is_empty()
+                || self.streams.contains_key(&channel.stream_id)
+                || !seen.insert(channel.stream_id.as_str())
+            {
+                continue;
+            }
+            let name = normalize(&channel.name);
+            if name.is_empty() {
+                continue;
+            }
+            // The best score of each guide channel
+            let mut scores: HashMap<&str, (f64, &str)> = HashMap::new();
+            let name_numbers = numbers(&name);
+            for ((n, id, display_name), n_numbers) in candidates.iter().zip(&candidate_numbers) {
+                if *n_numbers != name_numbers {
+                    continue;
+                }
+                let score = strsim::normalized_levenshtein(&name, n);
+                let best = scores.entry(id).or_insert((score, display_name));
+                if score > best.0 {
+                    *best = (score, display_name);
+                }
+            }
+            let mut ranked: Vec<_> = scores.into_iter().collect();
+            ranked.sort_by(|a, b| b.1.0.total_cmp(&a.1.0).then(a.0.cmp(b.0)));
+            let Some((id, (score, display_name))) = ranked.first() else {
+                continue;
+            };
+            let runner_up = ranked.get(1).map_or(0.0, |r| r.1.0);
+            if *score >= threshold && score - runner_up >= 0.05 {
+                matches.push(Match {
+                    stream_id: channel.stream_id.clone(),
+                    name: channel.name.clone(),
+                    epg_id: id.to_string(),
+                    display_name: display_name.to_string(),
+                    score: *score,
+                });
+            }
+        }
+        matches
+    }
+
+    // Add new matches to the end of the file, leaving what is there as it is
+    pub fn save(&mut self, new: &[Match]) -> std::io::Result<()> {
+        if self.text.is_empty() {
+            self.text.push_str(HEADER);
+        }
+        let quote = |s: &str| toml::Value::String(s.to_string()).to_string();
+        let comment = |s: &str| s.replace(['\r', '\n'], " ");
+        for m in new {
+            let _ = write!(
+                self.text,
+                "\n# {} -> {} ({:.2})\n{} = {}\n",
+                comment(&m.name),
+                comment(&m.display_name),
+                m.score,
+                quote(&m.stream_id),
+                quote(&m.epg_id)
+            );
+            self.streams.insert(m.stream_id.clone(), m.epg_id.clone());
+        }
+        // The guides are read before the live M3U files are written
+        if let Some(dir) = self.path.parent() {
+            create_dir_all(dir)?;
+        }
+        write_atomic(&self.path, &self.text, 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{Category, Channel};
+
+    fn channel(id: &str, name: &str, epg_id: &str) -> Channel {
+        Channel {
+            stream_id: id.to_string(),
+            name: name.to_string(),
+            category: "News".to_string(),
+            epg_id: epg_id.to_string(),
+            logo: "".to_string(),
+            ext: "".to_string(),
+            added: "".to_string(),
+            episode: None,
+        }
+    }
+
+    fn groups(channels: &[Channel]) -> Vec<Group> {
+        vec![Group {
+            category: Category::uncategorized("News"),
+            channels: Some(channels.to_vec()),
+            complete: true,
+        }]
+    }
+
+    fn guide(channels: &[(&str, &str)]) -> Vec<GuideChannel> {
+        channels
+            .iter()
+            .map(|(id, name)| GuideChannel {
+                id: id.to_string(),
+                names: vec![name.to_string()],
+            })
+            .collect()
+    }
+
+    fn find(name: &str, guide_channels: &[(&str, &str)], threshold: f64) -> Option<String> {
+        let found = EpgMatches::default().find(
+            &groups(&[channel("1", name, "")]),
+            &guide(guide_channels),
+            threshold,
+        );
+        found.first().map(|m| m.epg_id.clone())
+    }
+
+    #[test]
+    fn normalized_names() {
+        assert_eq!(normalize("UK: BBC One FHD"), "bbc one");
+        assert_eq!(normalize("[DE] Das Erste HD"), "das erste");
+        assert_eq!(normalize("|UK| Sky News 1080p"), "sky news");
+        assert_eq!(normalize("US| CNN (East)"), "cnn east");
+        assert_eq!(normalize("Sky Sports 1 4K"), "sky sports 1");
+        assert_eq!(normalize("HD"), "");
+    }
+
+    #[test]
+    fn numbers_of_names() {
+        assert_eq!(numbers("sky sports 1"), ["1"]);
+        assert_eq!(numbers("e4 extra"), ["4"]);
+        assert_eq!(numbers("bbc one"), Vec::<&str>::new());
+    }
+
+    #[test]
+    fn matches_without_prefix_and_quality() {
+        let channels = [("bbc1.uk", "BBC One"), ("bbc2.uk", "BBC Two")];
+        assert_eq!(
+            find("UK: BBC One FHD", &channels, 0.9).as_deref(),
+            Some("bbc1.uk")
+        );
+    }
+
+    #[test]
+    fn different_numbers_never_match() {
+        let channels = [("sky2", "Sky Sports 2")];
+        assert_eq!(find("Sky Sports 1", &channels, 0.5), None);
+        let channels = [("sky1", "Sky Sports 1"), ("sky2", "Sky Sports 2")];
+        assert_eq!(
+            find("UK: Sky Sports 1 HD", &channels, 0.9).as_deref(),
+            Some("sky1")
+        );
+        assert_eq!(find("Sky Sports", &channels, 0.5), None);
+    }
+
+    #[test]
+    fn threshold() {
+        let channels = [("news", "Sky News")];
+        assert_eq!(find("Sky Nws", &channels, 0.9), None);
+        assert_eq!(find("Sky Nws", &channels, 0.8).as_deref(), Some("news"));
+    }
+
+    #[test]
+    fn too_close_to_the_next_best() {
+        let channels = [("a", "Channel Alpha"), ("b", "Channel Alphi")];
+        assert_eq!(find("Channel Alph", &channels, 0.5), None);
+        // Two names of the same guide channel are not competing
+        let guide = vec![GuideChannel {
+            id: "a".to_string(),
+            names: vec!["Channel Alpha".to_string(), "Channel Alphi".to_string()],
+        }];
+        let found =
+            EpgMatches::default().find(&groups(&[channel("1", "Channel Alph", "")]), &guide, 0.5);
+        assert_eq!(found.len(), 1);
+    }
+
+    #[test]
+    fn only_streams_without_an_id() {
+        let mut matches = EpgMatches::default();
+        matches.streams.insert("2".to_string(), "".to_string());
+        let streams = [
+            channel("1", "BBC One", "bbc1.provider"),
+            channel("2", "BBC One", ""),
+            channel("3", "BBC One", ""),
+            channel("3", "BBC One", ""),
+        ];
+        let found = matches.find(&groups(&streams), &guide(&[("bbc1.uk", "BBC One")]), 0.9);
+        let ids: Vec<_> = found.iter().map(|m| m.stream_id.as_str()).collect();
+        assert_eq!(ids, ["3"]);
+        assert_eq!(found[0].score, 1.0);
+    }
+
+    #[test]
+    fn display_names_with_entities() {
+        let xml = br#"<tv><channel id="x"><display-name>Kids &amp; More</display-name>
+            <display-name>K&#38;M</display-name></channel>
+            <programme channel="x"/><channel id="late"/></tv>"#;
+        let channels = guide_channels(xml).unwrap();
+        assert_eq!(channels.len(), 1);
+        assert_eq!(channels[0].names, ["Kids & More", "K&M"]);
+    }
+}
@@ -0,0 +1,60 @@
+use std::fmt;
+
+#[derive(Debug)]
+pub enum Error {
+    Http(reqwest::Error),
+    Status(reqwest::StatusCode),
+    Json(serde_json::Error),
+    // Valid JSON that is not laid out the way the request expects
+    Shape(&'static str),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Http(e) => write!(f, "{e}"),
+            Error::Status(s) => write!(f, "server returned {s}"),
+            Error::Json(e) => write!(f, "bad response from the server: {e}"),
+            Error::Shape(what) => write!(f, "bad response from the server: not {what}"),
+        }
+    }
+}
+
+impl Error {
+    // Errors worth retrying: network problems, timeouts, rate limiting and server errors
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Error::Http(e) => e.is_timeout() || e.is_connect() || e.is_request() || e.is_body(),
+            Error::Status(s) => {
+                *s == reqwest::StatusCode::TOO_MANY_REQUESTS
+                    || *s == reqwest::StatusCode::REQUEST_TIMEOUT
+                    || s.is_server_error()
+            }
+            Error::Json(_) | Error::Shape(_) => false,
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Http(e) => Some(e),
+            Error::Json(e) => Some(e),
+            Error::Status(_) | Error::Shape(_) => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(e: reqwest::Error) -> Self {
+        Error::Http(e)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Self {
+        Error::Json(e)
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
@@ -0,0 +1,149 @@
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use std::fmt::Write;
+use std::fs::{create_dir_all, read_to_string};
+use std::path::{Path, PathBuf};
+
+use crate::atomic::write_atomic;
+use crate::diff::{Change, ChangeKind};
+use crate::report::escape;
+
+const FEED: &str = "new.atom";
+// The entries of the feed, so they can be carried over to the next run
+const ENTRIES: &str = ".xtream2m3u_feed.json";
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Entry {
+    pub id: String,
+    pub title: String,
+    pub category: String,
+    #[serde(default)]
+    pub series: Option<String>,
+    #[serde(default)]
+    pub image: String,
+    pub added: DateTime<Local>,
+}
+
+impl Entry {
+    // Streams without a usable added date are dated to the run that found them
+    pub fn from_change(
+        c: &Change,
+        group_type: &str,
+        provider: &str,
+        run: DateTime<Local>,
+    ) -> Entry {
+        let channel = &c.channel;
+        let added = channel
+            .added
+            .parse::<i64>()
+            .ok()
+            .and_then(|t| DateTime::from_timestamp(t, 0))
+            .map(|t| t.with_timezone(&Local))
+            .unwrap_or(run);
+        let image = match &channel.episode {
+            Some(e) if channel.logo.is_empty() => e.cover.clone(),
+            _ => channel.logo.clone(),
+        };
+        Entry {
+            id: format!(
+                "urn:xtream2m3u:{provider}:{group_type}:{}",
+                channel.stream_id
+            ),
+            title: channel.name.clone(),
+            category: channel.category.clone(),
+            series: channel.episode.as_ref().map(|e| e.series_name.clone()),
+            image,
+            added,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Feed {
+    pub entries: Vec<Entry>,
+}
+
+impl Feed {
+    pub fn load(dir: &Path) -> Feed {
+        read_to_string(dir.join(ENTRIES))
+            .ok()
+            .and_then(|s| serde_json::from_str(&s).ok())
+            .unwrap_or_default()
+    }
+
+    // Add the movies and episodes that were added in this run
+    pub fn add(
+        &mut self,
+        changes: &[Change],
+        group_type: &str,
+        provider: &str,
+        run: DateTime<Local>,
+    ) {
+        for c in changes.iter().filter(|c| c.kind == ChangeKind::Added) {
+            let entry = Entry::from_change(c, group_type, provider, run);
+            self.entries.retain(|e| e.id != entry.id);
+            self.entries.push(entry);
+        }
+    }
+
+    // Keep the newest max entries and write them out as an Atom feed
+    pub fn save(
+        &mut self,
+        dir: &Path,
+        provider: &str,
+        max: usize,
+        run: DateTime<Local>,
+    ) -> std::io::Result<PathBuf> {
+        self.entries
+            .sort_by(|a, b| b.added.cmp(&a.added).then(a.title.cmp(&b.title)));
+        self.entries.truncate(max);
+        create_dir_all(dir)?;
+        let contents = serde_json::to_string_pretty(self).map_err(std::io::Error::other)?;
+        write_atomic(&dir.join(ENTRIES), &contents, 0)?;
+        let name = dir.join(FEED);
+        write_atomic(&name, &self.to_atom(provider, run), 0)?;
+        Ok(name)
+    }
+
+    fn to_atom(&self, provider: &str, run: DateTime<Local>) -> String {
+        let updated = self.entries.first().map(|e| e.added).unwrap_or(run);
+        let mut xml = String::new();
+        let _ = writeln!(xml, "<?xml version=\"1.0\" encoding=\"utf-8\"?>");
+        let _ = writeln!(xml, "<feed xmlns=\"http://www.w3.org/2005/Atom\">");
+        let _ = writeln!(xml, "  <title>New on {}</title>", escape(provider));
+        let _ = writeln!(xml, "  <id>urn:xtream2m3u:{}:new</id>", escape(provider));
+        let _ = writeln!(xml, "  <updated>{}</updated>", updated.to_rfc3339());
+        let _ = writeln!(xml, "  <author><name>xtream2m3u</name></author>");
+        for e in &self.entries {
+            let title = match &e.series {
+                Some(s) if !e.title.contains(s.as_str()) => format!("{s}: {}", e.title),
+                _ => e.title.clone(),
+            };
+            let mut content = format!("<p>{}</p>", escape(&e.category));
+            if !e.image.is_empty() {
+                content = format!("<img src=\"{}\" alt=\"\">{content}", escape(&e.image));
+            }
+            let _ = writeln!(xml, "  <entry>");
+            let _ = writeln!(xml, "    <id>{}</id>", escape(&e.id));
+            let _ = writeln!(xml, "    <title>{}</title>", escape(&title));
+            let _ = writeln!(xml, "    <updated>{}</updated>", e.added.to_rfc3339());
+            let _ = writeln!(xml, "    <published>{}</published>", e.added.to_rfc3339());
+            let _ = writeln!(xml, "    <category term=\"{}\"/>", escape(&e.category));
+            if !e.image.is_empty() {
+                let _ = writeln!(
+                    xml,
+                    "    <link rel=\"enclosure\" href=\"{}\"/>",
+                    escape(&e.image)
+                );
+            }
+            let _ = writeln!(
+                xml,
+                "    <content type=\"html\">{}</content>",
+                escape(&content)
+            );
+            let _ = writeln!(xml, "  </entry>");
+        }
+        let _ = writeln!(xml, "</feed>");
+        xml
+    }
+}
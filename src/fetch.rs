@@ -0,0 +1,163 @@
+use futures::StreamExt;
+use std::collections::HashMap;
+use std::future::Future;
+
+use crate::error::Result;
+use crate::types::{Category, Channel, HasCategory, Listing, Skipped};
+
+// The channels of one category, ready to be written. channels is None when the category
+// could not be fetched, and complete is false when only part of it could be fetched.
+#[derive(Debug)]
+pub struct Group {
+    pub category: Category,
+    pub channels: Option<Vec<Channel>>,
+    pub complete: bool,
+}
+
+impl Group {
+    // Stands in for a category whose streams are not known this run, such as the
+    // uncategorized streams without the full list, so the ones seen before are kept
+    pub fn unknown(category: Category) -> Group {
+        Group {
+            category,
+            channels: None,
+            complete: false,
+        }
+    }
+
+    pub fn channels(&self) -> &[Channel] {
+        self.channels.as_deref().unwrap_or_default()
+    }
+}
+
+// Run f over items with at most `concurrency` requests in flight. Results are returned in
+// the same order as items so the generated files do not depend on response timing.
+pub async fn fetch_ordered<I, F, Fut>(items: I, concurrency: usize, f: F) -> Vec<Fut::Output>
+where
+    I: IntoIterator,
+    F: FnMut(I::Item) -> Fut,
+    Fut: Future,
+{
+    futures::stream::iter(items)
+        .map(f)
+        .buffered(concurrency.max(1))
+        .collect()
+        .await
+}
+
+// Streams for each category, in the same order as the categories. Streams with no
+// category_id or one that is not in the category list end up in uncategorized, which is
+// only known when the full list of streams was fetched.
+#[derive(Debug)]
+pub struct CategoryStreams<T> {
+    pub streams: Vec<Result<Vec<T>>>,
+    pub uncategorized: Option<Vec<T>>,
+    pub skipped: Vec<Skipped>,
+    // Why the bulk request was not used, when one was asked for
+    pub fallback: Option<String>,
+    // Why the full list could not be used to find the uncategorized streams
+    pub unknown: Option<String>,
+}
+
+impl<T> CategoryStreams<T> {
+    // Pair each category with its streams, with the uncategorized streams as an extra
+    // category at the end
+    pub fn into_groups(
+        self,
+        categories: &[Category],
+        uncategorized_name: &str,
+    ) -> Vec<(Category, Result<Vec<T>>)> {
+        let mut groups: Vec<_> = categories.iter().cloned().zip(self.streams).collect();
+        if let Some(u) = self.uncategorized
+            && !u.is_empty()
+        {
+            groups.push((Category::uncategorized(uncategorized_name), Ok(u)));
+        }
+        groups
+    }
+}
+
+// Get the streams of every category, either from the full list of streams grouped by
+// category_id (bulk) or with one request per category. A bulk request that fails or returns
+// nothing falls back to one request per category. Without bulk the full list, when given, is
+// fetched after the categories only for the streams that are in none of them. Servers that
+// do not support the full list are not an error, the uncategorized streams are just unknown.
+pub async fn fetch_by_category<'a, T, A, P, PF>(
+    categories: &'a [Category],
+    concurrency: usize,
+    bulk: bool,
+    all: Option<A>,
+    per_category: P,
+) -> CategoryStreams<T>
+where
+    T: HasCategory,
+    A: Future<Output = Result<Listing<T>>>,
+    P: FnMut(&'a Category) -> PF,
+    PF: Future<Output = Result<Listing<T>>>,
+{
+    let mut all = all;
+    let mut fallback = None;
+    if bulk && let Some(all) = all.take() {
+        match all.await {
+            Ok(listing) if !listing.items.is_empty() || categories.is_empty() => {
+                return group_by_category(listing, categories);
+            }
+            Ok(_) => fallback = Some("Bulk request returned no streams".to_string()),
+            Err(e) => fallback = Some(format!("Bulk request failed ({e})")),
+        }
+    }
+
+    let mut skipped = vec![];
+    let streams = fetch_ordered(categories, concurrency, per_category)
+        .await
+        .into_iter()
+        .map(|r| {
+            r.map(|listing| {
+                skipped.extend(listing.skipped);
+                listing.items
+            })
+        })
+        .collect();
+    // Streams in the full list that could not be read were already skipped in their category
+    let (uncategorized, unknown) = match all {
+        None => (None, None),
+        Some(all) => match all.await {
+            Ok(listing) if !listing.items.is_empty() || categories.is_empty() => {
+                (group_by_category(listing, categories).uncategorized, None)
+            }
+            Ok(_) => (None, Some("The full stream list is empty".to_string())),
+            Err(e) => (None, Some(format!("The full stream list failed ({e})"))),
+        },
+    };
+    CategoryStreams {
+        streams,
+        uncategorized,
+        skipped,
+        fallback,
+        unknown,
+    }
+}
+
+fn group_by_category<T: HasCategory>(
+    listing: Listing<T>,
+    categories: &[Category],
+) -> CategoryStreams<T> {
+    let mut by_id: HashMap<String, Vec<T>> = HashMap::new();
+    for item in listing.items {
+        let id = item.category_id().unwrap_or_default().to_string();
+        by_id.entry(id).or_default().push(item);
+    }
+    let streams = categories
+        .iter()
+        .map(|c| Ok(by_id.remove(&c.category_id).unwrap_or_default()))
+        .collect();
+    let mut uncategorized: Vec<(String, Vec<T>)> = by_id.into_iter().collect();
+    uncategorized.sort_by(|a, b| a.0.cmp(&b.0));
+    CategoryStreams {
+        streams,
+        uncategorized: Some(uncategorized.into_iter().flat_map(|(_, v)| v).collect()),
+        skipped: listing.skipped,
+        fallback: None,
+        unknown: None,
+    }
+}
@@ -0,0 +1,19 @@
+pub mod atomic;
+pub mod chan_group;
+pub mod client;
+pub mod diff;
+pub mod epg;
+pub mod epg_match;
+pub mod error;
+pub mod feed;
+pub mod fetch;
+pub mod manifest;
+pub mod notify;
+pub mod report;
+pub mod retention;
+pub mod retry;
+pub mod safeguard;
+pub mod types;
+
+pub use client::XtreamClient;
+pub use error::Error;
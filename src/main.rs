@@ -1,674 +1,1111 @@
-use chrono::DateTime;
-use clap::Parser;
-use serde_json::Value;
-use similar::{ChangeTag, TextDiff};
-use static_str_ops::static_format;
-use std::fmt::Write as FmtWrite;
-use std::{
-    fs::{File, create_dir_all, read_to_string},
-    io::Write,
-    path::PathBuf,
+use clap::{CommandFactory, FromArgMatches};
+
+mod args;
+mod config;
+
+use args::{Args, Command, OutputFormat};
+
+use chrono::{DateTime, Local};
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use xtream2m3u::chan_group::ChanGroup;
+use xtream2m3u::diff::{
+    Change, ChangeCounts, RunInfo, RunSummary, SeriesReport, Snapshot, TypeSummary, compare,
+    migrate, write_diff_files, write_structured_diff,
 };
+use xtream2m3u::epg::{Epg, EpgMap, Filter, read_source};
+use xtream2m3u::epg_match::{EpgMatches, GuideChannel, guide_channels, normalize};
+use xtream2m3u::feed::Feed;
+use xtream2m3u::fetch::{CategoryStreams, Group, fetch_by_category, fetch_ordered};
+use xtream2m3u::manifest::{Manifest, StaleAction};
+use xtream2m3u::notify::{Notification, Notifier};
+use xtream2m3u::report::{Section, write_html_report};
+use xtream2m3u::safeguard::{EXIT_REJECTED, Safeguard};
+use xtream2m3u::types::account::EXIT_EXPIRING;
+use xtream2m3u::types::series::Episodes;
+use xtream2m3u::types::{Category, Channel, EpgListing, LiveStream, Skipped};
+use xtream2m3u::{Error, XtreamClient};
 
-mod types;
-use types::series::*;
-
-#[derive(Parser, Debug, Clone)]
-#[command(version, about, long_about=None)]
-struct Args {
-    #[arg(short, long)]
-    server: String,
-    #[arg(short, long)]
-    username: String,
-    #[arg(short, long)]
-    password: String,
-    #[arg(
-        short,
-        long,
-        help = "Append .ts to stream URLs",
-		num_args = 0..=1,
-        default_value = "",
-        default_missing_value = ".ts",
-    )]
-    ts: String,
-    #[arg(short, long, help = "Create a M3U for each VOD category")]
-    vod: bool,
-    #[arg(long, help = "Create a M3U for Series")]
-    series: bool,
-    #[arg(short = 'T', long, help = "Modify the stream URL for use in TVHeadend")]
-    tvheadend_remux: bool,
-    #[arg(short, long, help = "Do not add a header to the M3U files")]
-    no_header: bool,
-    #[arg(short, long, help = "Create M3U/Diff for live channels")]
-    live: bool,
-    #[arg(short, long)]
-    account_info: bool,
-    #[arg(short, long)]
-    diff: bool,
-    #[arg(short, long, help = "Create M3U files")]
-    m3u: bool,
-    #[arg(short = 'S', long, help = "Create a single M3U file")]
-    single_m3u: bool,
-    #[arg(
-        short,
-        long,
-        help = "Where to save M3U/Diff files",
-        default_value = "."
-    )]
-    output_dir: String,
+#[derive(Debug, Default)]
+struct TypeStats {
+    streams: usize,
+    changes: ChangeCounts,
+    uncategorized: usize,
+    shows: Option<SeriesReport>,
+    items: Vec<Change>,
+    files: Vec<PathBuf>,
 }
 
-enum StreamType<'a> {
-    Value(Value),
-    Episode(&'a Episode),
+#[derive(Debug, Default)]
+struct Run {
+    started: DateTime<Local>,
+    expiring: bool,
+    timezone: String,
+    live: TypeStats,
+    vod: TypeStats,
+    series: TypeStats,
+    series_count: usize,
+    series_no_episodes: usize,
+    skipped: usize,
+    failures: Vec<String>,
+    rejected: Vec<String>,
+    alerts: Vec<String>,
+    stale: usize,
+    pruned_diffs: usize,
+    rolled_up_diffs: usize,
 }
 
-trait ValueExtensions {
-    fn get_name(&self) -> String;
-    fn get_epg_id(&self) -> String;
-    fn get_category_name(&self) -> &str;
-    fn get_category_id(&self) -> &str;
-    fn get_stream_id(&self) -> String;
-    fn get_series_name(&self) -> &str;
-    fn get_series_id(&self) -> String;
-    fn get_episode_id(&self) -> String;
-    fn expires(&self) -> String;
-
-    fn created(&self) -> String;
-    fn max_connections(&self) -> i64;
-    fn is_trial(&self) -> bool;
-    fn status(&self) -> &str;
-    fn active_cons(&self) -> &str;
-    fn get_ext(&self) -> String;
-    fn get_icon(&self) -> String;
+impl Run {
+    fn stats_mut(&mut self, group_type: &str) -> &mut TypeStats {
+        match group_type {
+            "live" => &mut self.live,
+            "movie" => &mut self.vod,
+            _ => &mut self.series,
+        }
+    }
 }
 
-impl ValueExtensions for Value {
-    fn get_name(&self) -> String {
-        self["name"].as_str().unwrap_or_default().to_string()
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let matches = Args::command().get_matches();
+    let mut args = Args::from_arg_matches(&matches)?;
+    if let Err(e) = config::resolve(&mut args, &matches) {
+        eprintln!("{e}");
+        std::process::exit(1);
+    }
+
+    if (args.live || args.vod) && (!args.m3u && !args.diff) {
+        eprintln!("You must use -m/--m3u and/or -d/--diff");
+        std::process::exit(1);
     }
-    fn get_epg_id(&self) -> String { self["epg_channel_id"].as_str().unwrap_or_default().to_string() }
-    fn get_category_name(&self) -> &str {
-        self["category_name"].as_str().unwrap_or_default()
+    if args.epg && !args.live {
+        eprintln!("--epg needs -l/--live");
+        std::process::exit(1);
     }
-    fn get_series_name(&self) -> &str {self["category_name"].as_str().unwrap_or_default()}
-    fn get_category_id(&self) -> &str {
-        self["category_id"].as_str().unwrap_or("-1")
+    if args.epg_auto_match && !args.epg {
+        eprintln!("--epg-auto-match needs --epg");
+        std::process::exit(1);
     }
-    fn get_series_id(&self) -> String {
-        if self["series_id"].is_null() {
-            return self.get_episode_id()
+    if args.feed && !args.diff {
+        eprintln!("--feed needs -d/--diff");
+        std::process::exit(1);
+    }
+    let epg_map = match args.epg_map.as_deref().map(|p| EpgMap::load(Path::new(p))) {
+        Some(Ok(map)) => map,
+        Some(Err(e)) => {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+        None => EpgMap::default(),
+    };
+    let client = XtreamClient::new(&args.server, &args.username, &args.password)
+        .with_retry(args.retry_policy())
+        .on_retry(|retry| eprintln!("{retry}"));
+
+    if let Some(Command::NowNext { channel }) = &args.command {
+        std::process::exit(now_next(&client, &args, channel).await);
+    }
+
+    let mut run = Run {
+        started: Local::now(),
+        ..Default::default()
+    };
+
+    match client.account_info().await {
+        Ok(account) => {
+            let user = &account.user_info;
+            if !user.is_active() {
+                run.alerts.push(format!(
+                    "Account status is {}",
+                    user.status.as_deref().unwrap_or("unknown")
+                ));
+            }
+            if let Some(warn) = args.warn_days
+                && user.expires_within(warn)
+            {
+                run.alerts.push(format!(
+                    "Account expires in {} days, on {}",
+                    user.days_left().unwrap_or_default(),
+                    user.expires()
+                ));
+            }
+            run.expiring = args.warn_days.is_some() && !run.alerts.is_empty();
+            run.timezone = account.server_info.timezone.clone().unwrap_or_default();
+            match args.format {
+                OutputFormat::Text => {
+                    println!("Account Information:");
+                    println!(" Created: {}", user.created());
+                    println!(" Expires: {}", user.expires());
+                    println!(" Status: {}", user.status.as_deref().unwrap_or_default());
+                    println!(
+                        " Active Connections: {}",
+                        user.active_cons.unwrap_or_default()
+                    );
+                    println!(
+                        " Max Connections: {}",
+                        user.max_connections.unwrap_or_default()
+                    );
+                    println!(" Trial: {}", user.is_trial.unwrap_or_default());
+                }
+                OutputFormat::Json => {
+                    let output = serde_json::json!({
+                        "user_info": account.user_info,
+                        "server_info": account.server_info,
+                        "days_left": user.days_left(),
+                        "alerts": run.alerts,
+                    });
+                    println!("{}", serde_json::to_string_pretty(&output)?);
+                }
+            }
+        }
+        Err(Error::Status(status)) => {
+            eprintln!("Error {status} getting account information");
+            eprintln!("Verify that your username and password are correct");
+            run.alerts
+                .push(format!("Error {status} getting account information"));
+            notify(&args, &run, ChangeCounts::default()).await;
+            std::process::exit(1);
         }
-        match self["series_id"].is_string() {
-            true => self["series_id"].as_str().unwrap().to_string(),
-            false => self["series_id"].as_i64().unwrap().to_string(),
+        Err(err) => {
+            eprintln!("Error getting account information: {err}");
+            run.alerts
+                .push(format!("Error getting account information: {err}"));
+            notify(&args, &run, ChangeCounts::default()).await;
+            std::process::exit(1);
         }
     }
-    fn get_episode_id(&self) -> String {
-        if self["id"].is_null() { return "-1".to_string() }
-        match self["id"].is_string() {
-            true => self["id"].as_str().unwrap().to_string(),
-            false => self["id"].as_i64().unwrap().to_string(),
+    // Keep stdout valid JSON for scripts
+    for a in &run.alerts {
+        match args.format {
+            OutputFormat::Text => println!("Warning: {a}"),
+            OutputFormat::Json => eprintln!("Warning: {a}"),
         }
     }
+    if args.account_info {
+        notify(&args, &run, ChangeCounts::default()).await;
+        std::process::exit(if run.expiring { EXIT_EXPIRING } else { 0 });
+    }
 
-    fn get_stream_id(&self) -> String {
-        match self["stream_id"].is_string() {
-            true => self["stream_id"].as_str().unwrap().to_string(),
-            false => self["stream_id"].as_i64().unwrap().to_string(),
+    if args.live
+        && let Some(mut groups) = fetch_live(&client, &args, &mut run).await
+    {
+        epg_map.apply(&mut groups);
+        // Matches found by earlier --epg-auto-match runs are used by every run
+        let mut matches = match EpgMatches::load(&args.m3u_dir("live")) {
+            Ok(matches) => Some(matches),
+            Err(e) => {
+                println!("{e}");
+                None
+            }
+        };
+        if let Some(matches) = &matches {
+            matches.apply(&mut groups);
+        }
+        let guides = match args.epg {
+            true => fetch_guides(&client, &args, &mut run).await,
+            false => None,
+        };
+        if args.epg_auto_match
+            && let Some(guides) = &guides
+            && let Some(matches) = &mut matches
+        {
+            auto_match(&args, &mut groups, guides, matches);
         }
+        update_type(&args, "live", &groups, &mut run);
+        if let Some(guides) = &guides {
+            update_epg(&args, &groups, &epg_map, guides, &mut run);
+        }
+    }
+    if args.vod
+        && let Some(groups) = fetch_vod(&client, &args, &mut run).await
+    {
+        update_type(&args, "movie", &groups, &mut run);
+    }
+    if args.series
+        && let Some(groups) = fetch_series(&client, &args, &mut run).await
+    {
+        update_type(&args, "series", &groups, &mut run);
     }
 
-    fn get_ext(&self) -> String {
-        let x = self["container_extension"].as_str().unwrap_or_default();
-        match x.is_empty() {
-            true => "".to_string(),
-            false => format!(".{x}"),
+    let (live, vod, series) = (&run.live, &run.vod, &run.series);
+    let mut total = live.changes;
+    total.add(&vod.changes);
+    total.add(&series.changes);
+    if args.m3u {
+        if args.live {
+            println!("Live Streams: {}", live.streams);
+        }
+        if args.vod {
+            println!("VOD Streams: {}", vod.streams);
         }
+        if args.series {
+            println!("Series: {}", run.series_count);
+            println!("Episode Streams: {}", series.streams);
+            println!("Series with no episodes: {}", run.series_no_episodes);
+        }
+        println!(
+            "Total Streams: {}",
+            live.streams + vod.streams + series.streams
+        );
+        if run.skipped > 0 {
+            println!("Skipped bad entries: {}", run.skipped);
+        }
+    }
+
+    if live.uncategorized + vod.uncategorized + series.uncategorized > 0 {
+        println!(
+            "Streams with no known category added to {}: Live {}, VOD {}, Series {}",
+            args.uncategorized_name, live.uncategorized, vod.uncategorized, series.uncategorized
+        );
     }
-    
-    fn get_icon(&self) -> String {
-        let x = self["stream_icon"].as_str().unwrap_or_default();
-        match x.is_empty() {
-           true => "".to_string(),
-            false => x.to_string(),
+
+    if run.stale > 0 {
+        match (args.stale, args.stale_dry_run) {
+            (StaleAction::Keep, _) => println!(
+                "Stale M3U files of removed categories: {} (use --stale delete or --stale archive)",
+                run.stale
+            ),
+            (_, true) => println!("Stale M3U files that would be removed: {}", run.stale),
+            (_, false) => println!("Stale M3U files removed: {}", run.stale),
         }
     }
-    fn expires(&self) -> String {
-        let exp_ts = match self["user_info"]["exp_date"].as_str() {
-            Some(s) => s.parse().unwrap(),
-            _ => self["user_info"]["exp_date"].as_i64().unwrap_or_default(),
-        };
-        DateTime::from_timestamp(exp_ts, 0)
-            .unwrap_or_default()
-            .to_string()
-    }
-    fn created(&self) -> String {
-        let created_ts = match self["user_info"]["created_at"].as_str() {
-            Some(s) => s.parse().unwrap(),
-            _ => self["user_info"]["created_at"].as_i64().unwrap_or_default(),
-        };
-        DateTime::from_timestamp(created_ts, 0)
-            .unwrap_or_default()
-            .to_string()
+
+    if run.pruned_diffs > 0 {
+        println!(
+            "Old diff files removed: {}, added to changelogs: {}",
+            run.pruned_diffs, run.rolled_up_diffs
+        );
     }
-    fn max_connections(&self) -> i64 {
-        match self["user_info"]["max_connections"].as_str() {
-            Some(s) => s.parse().unwrap(),
-            _ => self["user_info"]["max_connections"]
-                .as_i64()
-                .unwrap_or_default(),
+
+    if !run.failures.is_empty() {
+        println!("{} requests failed after retries:", run.failures.len());
+        for f in &run.failures {
+            println!(" {f}");
         }
     }
-    fn is_trial(&self) -> bool {
-        match self["user_info"]["is_trial"].is_boolean() {
-            true => self["user_info"]["is_trial"].as_bool().unwrap(),
-            false => matches!(self["user_info"]["is_trial"].as_str(), Some("1")),
+
+    if args.diff {
+        if args.live {
+            println!("Live channel changes: {}", describe_changes(&live.changes));
+        }
+        if args.vod {
+            println!("VOD channel changes: {}", describe_changes(&vod.changes));
+        }
+        if args.series {
+            println!(
+                "Series episode changes: {}",
+                describe_changes(&series.changes)
+            );
+            if let Some(shows) = &series.shows {
+                println!(
+                    "Series show changes: New {}, Removed {}",
+                    shows.added.len(),
+                    shows.removed.len()
+                );
+                for line in shows.lines(None) {
+                    println!(" {line}");
+                }
+            }
+        }
+        println!("Total changed: {}", describe_changes(&total));
+        if args.diff_format.is_some() {
+            save_summary(&args, &run, total);
+        }
+        if args.html_report {
+            save_report(&args, &run);
+        }
+        if args.feed && (args.vod || args.series) {
+            save_feed(&args, &run);
         }
     }
-    fn status(&self) -> &str {
-        self["user_info"]["status"].as_str().unwrap_or_default()
+
+    notify(&args, &run, total).await;
+
+    if !run.rejected.is_empty() {
+        println!("Files were not updated for:");
+        for r in &run.rejected {
+            println!(" {r}");
+        }
+        std::process::exit(EXIT_REJECTED);
     }
-    fn active_cons(&self) -> &str {
-        self["user_info"]["active_cons"]
-            .as_str()
-            .unwrap_or_default()
+    if run.expiring {
+        std::process::exit(EXIT_EXPIRING);
     }
+    Ok(())
 }
 
-#[derive(Debug)]
-struct ChanGroup {
-    args: Args,
-    group_name: String,
-    file_name: String,
-    file_created: bool,
-    m3u_dir: PathBuf,
-    diff_dir: PathBuf,
-    handle: Option<File>,
-    all_channels: Vec<String>,
-    group_type: String,
+// The live stream a name or stream id stands for: an exact name, else the only stream whose
+// name matches or contains it once prefixes and quality tags are left out, else the only one
+// that contains it as typed. When that is not a single stream the candidates are returned.
+fn find_stream<'a>(
+    streams: &'a [LiveStream],
+    query: &str,
+) -> Result<&'a LiveStream, Vec<&'a LiveStream>> {
+    if let Some(s) = streams
+        .iter()
+        .find(|s| s.stream_id == query || s.name.eq_ignore_ascii_case(query))
+    {
+        return Ok(s);
+    }
+    let raw = query.to_lowercase();
+    let query = normalize(query);
+    let same: Vec<_> = streams
+        .iter()
+        .filter(|s| normalize(&s.name) == query)
+        .collect();
+    let mut found = match same.is_empty() {
+        false => same,
+        true => streams
+            .iter()
+            .filter(|s| !query.is_empty() && normalize(&s.name).contains(&query))
+            .collect(),
+    };
+    if found.is_empty() {
+        found = streams
+            .iter()
+            .filter(|s| s.name.to_lowercase().contains(&raw))
+            .collect();
+    }
+    match found.as_slice() {
+        [s] => Ok(s),
+        _ => Err(found),
+    }
 }
 
-impl ChanGroup {
-    fn new(args: Args, group_name: String, group_type: String) -> ChanGroup {
-        let m3u_dir: PathBuf = [&args.output_dir, &format!("{group_type}_m3u")].iter().collect();
-        let diff_dir: PathBuf = [&args.output_dir, &format!("{group_type}_m3u")].iter().collect();
-        let file_name = match args.single_m3u {
-            false => sanitise_file_name::sanitise(static_format!("{group_name}.m3u")).to_string(),
-            true => sanitise_file_name::sanitise(static_format!("all.m3u")).to_string(),
-        };
-
-        ChanGroup {
-            args,
-            group_name,
-            file_name,
-            file_created: false,
-            m3u_dir,
-            diff_dir,
-            handle: None,
-            all_channels: vec![],
-            group_type,
-        }
+fn print_listing(label: &str, listing: Option<&EpgListing>) {
+    let Some(l) = listing else {
+        println!("{label}: no programme");
+        return;
+    };
+    let time = |ts: Option<i64>, text: &str| match ts.and_then(|t| DateTime::from_timestamp(t, 0)) {
+        Some(t) => t.with_timezone(&Local).format("%H:%M").to_string(),
+        None => text.to_string(),
+    };
+    println!(
+        "{label}: {}-{} {}",
+        time(l.start_timestamp, &l.start),
+        time(l.stop_timestamp, &l.end),
+        l.title
+    );
+    if !l.description.is_empty() {
+        println!("      {}", l.description.replace('\n', " "));
     }
+}
 
-    fn create_file(&mut self) -> std::io::Result<()> {
-        if self.args.m3u {
-            if let Ok(false) = std::fs::exists(&self.m3u_dir) {
-                println!("Creating {:?}", self.m3u_dir);
-                let _ = create_dir_all(&self.m3u_dir);
-            };
+// The now-next command, returns the exit status
+async fn now_next(client: &XtreamClient, args: &Args, query: &str) -> i32 {
+    // A stream id is asked for directly, only a name needs the channel list
+    let stream = match query.parse::<u64>() {
+        Ok(_) => None,
+        Err(_) => match live_stream(client, args, query).await {
+            Some(stream) => Some(stream),
+            None => return 1,
+        },
+    };
+    let (stream_id, name) = match &stream {
+        Some(s) => (s.stream_id.as_str(), s.name.as_str()),
+        None => (query, query),
+    };
+    let epg = match client.short_epg(stream_id, 4).await {
+        Ok(epg) => epg,
+        Err(err) => {
+            eprintln!("Error getting EPG of {name}: {err}");
+            return 1;
         }
-        
-        if !self.file_created {
-            println!("Creating {:?}", self.file_name);
-            self.handle = match File::create(self.m3u_dir.join(self.file_name.clone())) {
-                Ok(f) => Some(f),
-                Err(e) => panic!("Error creating : {e:?}"),
-            };
-            if !self.args.no_header {
-                if let Some(ref mut h) = self.handle {
-                    writeln!(h, "#EXTM3U")?;
-                }
+    };
+    let (now, next) = epg.now_next(Local::now().timestamp());
+    match args.format {
+        OutputFormat::Text => {
+            match &stream {
+                Some(s) => println!("{} (stream {})", s.name, s.stream_id),
+                None => println!("Stream {stream_id}"),
             }
-            self.file_created = true;
-        } else {
-            println!("Using {:?}", self.handle);
-        }
-        Ok(())
-    }
-
-    fn add_channel(&mut self, gname: String, chan: StreamType) -> std::io::Result<()> {
-        let mut epg_id: String = String::from("");
-        let chan_name: String;
-        let stream_icon: String;
-        let ext: String;
-        let stream_id: String;
-
-        match chan {
-            StreamType::Value(chan) => {
-                chan_name = chan.get_name().clone();
-                epg_id = chan.get_epg_id();
-                ext = chan.get_ext();
-                stream_id = chan.get_stream_id();
-                stream_icon = chan.get_icon();
-            },
-            StreamType::Episode(chan) => {
-                chan_name = chan.title.clone();
-                ext = chan.ext();
-                stream_id = chan.id.clone();
-                stream_icon = "".to_string();
-            }};
-        self.all_channels.push(chan_name.clone());
-        if self.args.m3u {
-            if let Some(ref mut h) = self.handle {
-                writeln!(
-                    h,
-                    "#EXTINF:-1 tvg-id=\"{}\" tvg-name=\"{}\" tvg-logo=\"{}\" group-title=\"{}\",\
-                    {}",
-                    epg_id.trim_start_matches("\"").trim_end_matches("\""),
-                    chan_name.trim_start_matches("\"").trim_end_matches("\""),
-                    stream_icon.trim_start_matches("\"").trim_end_matches("\""),
-                    gname.trim_start_matches("\"").trim_end_matches("\""),
-                    chan_name.trim_start_matches("\"").trim_end_matches("\""),
-                )?;
-
-                let mut server = self.args.server.clone();
-                if self.group_type != "live" {
-                    server += &*format!("/{}", self.group_type);
+            print_listing("Now", now);
+            print_listing("Next", next);
+        }
+        OutputFormat::Json => {
+            let output = serde_json::json!({
+                "stream_id": stream_id,
+                "name": stream.as_ref().map(|s| &s.name),
+                "epg_channel_id": stream.as_ref().map(|s| &s.epg_channel_id),
+                "now": now,
+                "next": next,
+            });
+            match serde_json::to_string_pretty(&output) {
+                Ok(json) => println!("{json}"),
+                Err(e) => {
+                    eprintln!("{e}");
+                    return 1;
                 }
-                //if !ext.is_empty() {
-                //    ext = format!(".{}", ext);
-                //}
-                writeln!(
-                    h,
-                    "{}/{}/{}/{}{}",
-                    server,
-                    self.args.username,
-                    self.args.password,
-                    stream_id,
-                    ext
-                )?;
             }
         }
-        Ok(())
     }
-    fn make_diff_file(&mut self) -> Result<(u32, u32), std::io::Error> {
-        let mut new_contents = String::new();
+    0
+}
 
-        let now = chrono::offset::Local::now()
-            .format("%Y%m%d_%H%M%S")
-            .to_string();
-        if let Ok(false) = std::fs::exists(&self.diff_dir) {
-            println!("Creating {:?}", self.diff_dir);
-            let _ = create_dir_all(&self.diff_dir);
-        };
-        let all_name = self
-            .diff_dir
-            .join(sanitise_file_name::sanitise(static_format!(
-                "{}_all.txt",
-                self.group_name
-            )));
-        let diff_name = self
-            .diff_dir
-            .join(sanitise_file_name::sanitise(static_format!(
-                "{}_diff_{now}.txt",
-                self.group_name
-            )));
-        let all_exists = std::fs::exists(&all_name)?;
-        let original_contents = read_to_string(&all_name).unwrap_or_default();
-        let mut all_handle = match File::create(&all_name) {
-            Ok(f) => f,
-            Err(e) => panic!("Error creating {all_name:?}: {e:?}"),
-        };
-        self.all_channels.sort();
-        for c in self.all_channels.clone() {
-            writeln!(all_handle, "{c}")?;
-            let _ = writeln!(&mut new_contents, "{c}");
+// Find a live channel by name, getting the channel list the same way as an update: in one
+// request, or one request per category when the server does not support that
+async fn live_stream(client: &XtreamClient, args: &Args, query: &str) -> Option<LiveStream> {
+    let categories = match client.live_categories().await {
+        Ok(c) => c.items,
+        Err(err) => {
+            eprintln!("Error getting live categories: {err}");
+            vec![]
         }
+    };
+    let fetched = fetch_by_category(
+        &categories,
+        args.concurrency,
+        true,
+        Some(client.all_live_streams()),
+        |c| client.live_streams(&c.category_id),
+    )
+    .await;
+    if let Some(reason) = &fetched.fallback {
+        eprintln!("{reason}, using one request per category");
+    }
+    let mut streams = vec![];
+    for (c, result) in fetched.into_groups(&categories, &args.uncategorized_name) {
+        match result {
+            Ok(s) => streams.extend(s),
+            Err(err) => eprintln!("Error getting live streams for {}: {err}", c.category_name),
+        }
+    }
+    match find_stream(&streams, query) {
+        Ok(stream) => Some(stream.clone()),
+        Err(found) if found.is_empty() => {
+            eprintln!("No live channel matches {query:?}");
+            None
+        }
+        Err(found) => {
+            eprintln!("{} live channels match {query:?}, use one of:", found.len());
+            for s in found.iter().take(20) {
+                eprintln!(" {} {}", s.stream_id, s.name);
+            }
+            None
+        }
+    }
+}
 
-        let mut changes: u32 = 0;
-        let mut inserted: u32 = 0;
-        let mut deleted: u32 = 0;
+// The guides of a run. They are read before the live files are written, so EPG ids found by
+// --epg-auto-match end up in the M3U.
+struct Guides {
+    provider: Vec<u8>,
+    sources: Vec<(String, Vec<u8>)>,
+}
 
-        if all_exists {
-            let cdiff = TextDiff::from_lines(&original_contents, &new_contents);
+async fn fetch_guides(client: &XtreamClient, args: &Args, run: &mut Run) -> Option<Guides> {
+    println!("Getting EPG");
+    let provider = match client.xmltv().await {
+        Ok(xml) => xml,
+        Err(err) => {
+            println!("Error getting EPG: {err}");
+            run.failures.push(format!("EPG: {err}"));
+            return None;
+        }
+    };
+    let mut sources = vec![];
+    for source in &args.epg_source {
+        match read_source(Path::new(source)) {
+            Ok(xml) => sources.push((source.clone(), xml)),
+            Err(err) => {
+                println!("Error reading EPG {source}: {err}");
+                run.failures.push(format!("EPG {source}: {err}"));
+            }
+        }
+    }
+    Some(Guides { provider, sources })
+}
 
-            if cdiff.ratio() < 1.0 {
-                let mut diff_output = match File::create(&diff_name) {
-                    Ok(f) => f,
-                    Err(e) => panic!("Error creating diff file {diff_name:?}: {e:?}"),
-                };
-                for change in cdiff.iter_all_changes() {
-                    match change.tag() {
-                        ChangeTag::Delete => {
-                            deleted += 1;
-                            write!(diff_output, "- {}", change.value())?;
-                        }
-                        ChangeTag::Insert => {
-                            inserted += 1;
-                            write!(diff_output, "+ {}", change.value())?;
-                        }
-                        _ => (),
-                    };
-                    changes = inserted + deleted;
-                }
+fn auto_match(args: &Args, groups: &mut [Group], guides: &Guides, matches: &mut EpgMatches) {
+    // Guides that cannot be read are reported when they are filtered
+    let channels: Vec<GuideChannel> = std::iter::once(&guides.provider)
+        .chain(guides.sources.iter().map(|(_, xml)| xml))
+        .filter_map(|xml| guide_channels(xml).ok())
+        .flatten()
+        .collect();
+    let found = matches.find(groups, &channels, args.epg_match_threshold);
+    if found.is_empty() {
+        return;
+    }
+    match matches.save(&found) {
+        Ok(()) => println!(
+            "Matched {} channels to EPG ids, review them in {:?}",
+            found.len(),
+            matches.path()
+        ),
+        Err(e) => println!("Error saving EPG matches: {e}"),
+    }
+    matches.apply(groups);
+}
+
+fn update_epg(args: &Args, groups: &[Group], map: &EpgMap, guides: &Guides, run: &mut Run) {
+    let filter = match Filter::new(
+        groups,
+        args.epg_past_days,
+        args.epg_future_days,
+        args.epg_timezone.as_deref(),
+        &run.timezone,
+    ) {
+        Ok(filter) => filter,
+        Err(err) => {
+            println!("Error with EPG timezone: {err}");
+            run.failures.push(format!("EPG: {err}"));
+            return;
+        }
+    };
+    let mut epg = match Epg::filter(&guides.provider, &filter) {
+        Ok(epg) => epg,
+        Err(err) => {
+            println!("Error reading EPG: {err}");
+            run.failures.push(format!("EPG: {err}"));
+            return;
+        }
+    };
+
+    // The provider's guide comes first, external guides only add channels it does not have
+    let (ids, unknown) = map.resolve(groups);
+    if !unknown.is_empty() {
+        println!(
+            "{} stream ids of the EPG map are not in the live playlists",
+            unknown.len()
+        );
+    }
+    for (source, xml) in &guides.sources {
+        let external = filter.external(&ids, &epg.found);
+        match Epg::filter(xml, &external) {
+            Ok(other) => {
                 println!(
-                    "Added {inserted}, Deleted {deleted}, Total {changes} saved to {diff_name:?}"
+                    "Merged {} channels and {} programmes from {source}",
+                    other.channels, other.programmes
                 );
-            } else {
-                println!("No changes for {}", self.group_name);
+                epg.merge(other);
+            }
+            Err(err) => {
+                println!("Error reading EPG {source}: {err}");
+                run.failures.push(format!("EPG {source}: {err}"));
             }
-        } else {
-            println!("Not creating diff file since no previous file exists");
         }
-        Ok((inserted, deleted))
     }
-}
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let args = Args::parse();
 
-    if (args.live || args.vod) && (!args.m3u && !args.diff) {
-        eprintln!("You must use -m/--m3u and/or -d/--diff");
-        std::process::exit(1);
+    match epg.save(&args.m3u_dir("live"), args.epg_gzip) {
+        Ok(name) => println!(
+            "Saved EPG with {} channels and {} programmes to {name:?}",
+            epg.channels, epg.programmes
+        ),
+        Err(e) => println!("Error saving EPG: {e}"),
     }
-    let account_url = format!(
-        "{}/player_api.php?username={}&password={}",
-        args.server, args.username, args.password
-    );
-    let category_url = format!(
-        "{}/player_api.php?username={}&password={}&action=get_live_categories",
-        args.server, args.username, args.password
-    );
-    let stream_by_category_url = format!(
-        "{}/player_api.php?username={}&password={}&action=get_live_streams&category_id=",
-        args.server, args.username, args.password
-    );
-    let vod_categories_url = format!(
-        "{}/player_api.php?username={}&password={}&action=get_vod_categories",
-        args.server, args.username, args.password
-    );
-    let vod_streams_url = format!(
-        "{}/player_api.php?username={}&password={}&action=get_vod_streams&category_id=",
-        args.server, args.username, args.password
-    );
-    let series_categories_url = format!(
-        "{}/player_api.php?username={}&password={}&action=get_series_categories",
-        args.server, args.username, args.password
+    let missing = epg.missing(&filter);
+    if !missing.is_empty() {
+        println!("No EPG for {} channel ids", missing.len());
+    }
+}
+
+async fn fetch_live(client: &XtreamClient, args: &Args, run: &mut Run) -> Option<Vec<Group>> {
+    println!("Getting categories");
+    let categories = match client.live_categories().await {
+        Ok(c) => c,
+        Err(err) => {
+            println!("Error getting live categories: {err}");
+            run.failures.push(format!("Live categories: {err}"));
+            return None;
+        }
+    };
+    run.skipped += report_skipped(&categories.skipped);
+    println!("Found {} categories", categories.items.len());
+    let fetched = fetch_by_category(
+        &categories.items,
+        args.concurrency,
+        args.bulk,
+        args.full_list().then(|| client.all_live_streams()),
+        |c| client.live_streams(&c.category_id),
+    )
+    .await;
+    report_fallback(&fetched);
+    run.skipped += report_skipped(&fetched.skipped);
+    run.live.uncategorized += fetched.uncategorized.as_ref().map_or(0, Vec::len);
+    let unknown = fetched.uncategorized.is_none();
+    let pairs = fetched.into_groups(&categories.items, &args.uncategorized_name);
+    let mut groups = stream_groups(
+        pairs,
+        "Live",
+        &mut run.live,
+        &mut run.failures,
+        Channel::from_live,
     );
-    let series_streams_url = format!(
-        "{}/player_api.php?username={}&password={}&action=get_series&category_id=",
-        args.server, args.username, args.password
+    keep_uncategorized(&mut groups, args, unknown);
+    Some(groups)
+}
+
+async fn fetch_vod(client: &XtreamClient, args: &Args, run: &mut Run) -> Option<Vec<Group>> {
+    println!("Getting VOD categories");
+    let categories = match client.vod_categories().await {
+        Ok(c) => c,
+        Err(err) => {
+            println!("Error getting VOD categories: {err}");
+            run.failures.push(format!("VOD categories: {err}"));
+            return None;
+        }
+    };
+    run.skipped += report_skipped(&categories.skipped);
+    println!("Found {} VOD categories", categories.items.len());
+    let fetched = fetch_by_category(
+        &categories.items,
+        args.concurrency,
+        args.bulk,
+        args.full_list().then(|| client.all_vod_streams()),
+        |c| client.vod_streams(&c.category_id),
+    )
+    .await;
+    report_fallback(&fetched);
+    run.skipped += report_skipped(&fetched.skipped);
+    run.vod.uncategorized += fetched.uncategorized.as_ref().map_or(0, Vec::len);
+    let unknown = fetched.uncategorized.is_none();
+    let pairs = fetched.into_groups(&categories.items, &args.uncategorized_name);
+    let mut groups = stream_groups(
+        pairs,
+        "VOD",
+        &mut run.vod,
+        &mut run.failures,
+        Channel::from_vod,
     );
-    let series_info_url = format!(
-        "{}/player_api.php?username={}&password={}&action=get_series_info&series_id=",
-        args.server, args.username, args.password);
-
-    let mut live_inserted = 0;
-    let mut live_deleted = 0;
-    let mut vod_inserted = 0;
-    let mut vod_deleted = 0;
-    let mut live_streams = 0;
-    let mut vod_streams = 0;
-    let mut series_inserted = 0;
-    let mut series_deleted = 0;
-    let mut series_streams = 0;
-    let mut episodes_inserted = 0;
-    let mut series_no_episodes = 0;
-
-    match reqwest::get(account_url).await {
-        Ok(resp) => {
-            if resp.status() != 200 {
-                println!("Error {} getting account information", resp.status());
-                println!("Verify that your username and password are correct");
-                std::process::exit(1);
-            }
-            let a_json = match resp.json::<Value>().await {
-                Ok(j) => j,
-                Err(e) => {
-                    println!("Error getting account information: {e}");
-                    std::process::exit(1);
-                }
-            };
+    keep_uncategorized(&mut groups, args, unknown);
+    Some(groups)
+}
 
-            println!("Account Information:");
-            println!(" Created: {}", a_json.created());
-            println!(" Expires: {}", a_json.expires());
-            println!(" Status: {}", a_json.status());
-            println!(" Active Connections: {}", a_json.active_cons());
-            println!(" Max Connections: {}", a_json.max_connections());
-            println!(" Trial: {}", a_json.is_trial());
+fn stream_groups<T>(
+    pairs: Vec<(Category, xtream2m3u::error::Result<Vec<T>>)>,
+    label: &str,
+    stats: &mut TypeStats,
+    failures: &mut Vec<String>,
+    to_channel: fn(&T, &str) -> Channel,
+) -> Vec<Group> {
+    let mut groups = vec![];
+    for (c, result) in pairs {
+        match result {
+            Ok(streams) => {
+                println!("Found {} streams in {}", streams.len(), c.category_name);
+                stats.streams += streams.len();
+                let channels = streams
+                    .iter()
+                    .map(|s| to_channel(s, &c.category_name))
+                    .collect();
+                groups.push(Group {
+                    category: c,
+                    channels: Some(channels),
+                    complete: true,
+                });
+            }
+            Err(err) => {
+                println!("Error getting streams for {}: {err}", c.category_name);
+                failures.push(format!("{label} category {}: {err}", c.category_name));
+                groups.push(Group {
+                    category: c,
+                    channels: None,
+                    complete: false,
+                });
+            }
         }
-        Err(err) => {
-            println!("Error: {err:?}");
-            std::process::exit(1);
-        },
     }
-    if args.account_info {
-        std::process::exit(0);
-    }
-
-    if args.live {
-        let c_json: Vec<Value>;
-        println!("Getting categories");
-        
-        match reqwest::get(category_url).await {
-            Ok(resp) => {
-                c_json = resp.json::<Vec<Value>>().await?;
-                println!("Found {} categories", c_json.len());
-                let mut chan_group : ChanGroup = ChanGroup::new(
-                    args.clone(),
-                    "ALL".to_string(),
-                    "live".to_string(),
-                );
-                if args.single_m3u && args.m3u {
-                    let _ = chan_group.create_file();
+    groups
+}
+
+async fn fetch_series(client: &XtreamClient, args: &Args, run: &mut Run) -> Option<Vec<Group>> {
+    println!("Getting Series categories");
+    let categories = match client.series_categories().await {
+        Ok(c) => c,
+        Err(err) => {
+            println!("Error getting series categories: {err}");
+            run.failures.push(format!("Series categories: {err}"));
+            return None;
+        }
+    };
+    run.skipped += report_skipped(&categories.skipped);
+    println!("Found {} Series categories", categories.items.len());
+    let fetched = fetch_by_category(
+        &categories.items,
+        args.concurrency,
+        args.bulk,
+        args.full_list().then(|| client.all_series()),
+        |c| client.series(&c.category_id),
+    )
+    .await;
+    report_fallback(&fetched);
+    run.skipped += report_skipped(&fetched.skipped);
+    run.series.uncategorized += fetched.uncategorized.as_ref().map_or(0, Vec::len);
+    let unknown = fetched.uncategorized.is_none();
+
+    let mut groups = vec![];
+    for (c, result) in fetched.into_groups(&categories.items, &args.uncategorized_name) {
+        let series_json = match result {
+            Ok(s) => s,
+            Err(err) => {
+                println!("Error getting series for {}: {err}", c.category_name);
+                run.failures
+                    .push(format!("Series category {}: {err}", c.category_name));
+                groups.push(Group {
+                    category: c,
+                    channels: None,
+                    complete: false,
+                });
+                continue;
+            }
+        };
+        println!("Found {} series in {}", series_json.len(), c.category_name);
+        run.series_count += series_json.len();
+
+        let infos = fetch_ordered(&series_json, args.concurrency, |series| {
+            client.series_info(&series.series_id)
+        })
+        .await;
+        let mut channels = vec![];
+        let mut complete = true;
+        for (series, info) in series_json.iter().zip(infos) {
+            match info {
+                Ok(v) => {
+                    let episodes = v.get_episodes();
+                    run.skipped += report_skipped(&episodes.skipped);
+                    let z = episodes.items;
+                    println!("{} has {} Episodes", v.series_name(), z.len());
+                    if z.is_empty() {
+                        run.series_no_episodes += 1
+                    }
+                    let name = v.series_name().trim_matches('"').to_string();
+                    channels.extend(
+                        z.iter()
+                            .map(|a| Channel::from_episode(a, series, &name, &c.category_name)),
+                    );
                 }
-                for c in &c_json {
-                    match reqwest::get(format!("{}{}", stream_by_category_url, c.get_category_id()))
-                        .await
-                    {
-                        Ok(s_resp) => {
-                            let s_json = s_resp.json::<Vec<Value>>().await?;
-                            println!(
-                                "Found {} streams in {}",
-                                s_json.len(),
-                                c.get_category_name()
-                            );
-                            live_streams += s_json.len();
-                            
-                            if !args.single_m3u {
-                                chan_group = ChanGroup::new(
-                                    args.clone(),
-                                    c.get_category_name().to_string(),
-                                    "live".to_string(),
-                                );
-                                if args.m3u {
-                                    let _ = chan_group.create_file();
-                                }
-                            }
-                            for stream in &s_json {
-                                let _ = chan_group
-                                    .add_channel(c.get_category_name().to_string(),
-                                                 StreamType::Value(stream.clone
-                                    ()));
-                            }
-                            if args.diff {
-                                (live_inserted, live_deleted) = match chan_group.make_diff_file() {
-                                    Ok((i, d)) => (i + live_inserted, d + live_deleted),
-                                    Err(_) => (live_inserted, live_deleted),
-                                };
-                            }
-                        }
-                        Err(err) => println!("Error {err:?}"),
-                    };
+                Err(err) => {
+                    println!("Error getting episodes for {}: {err}", series.name);
+                    run.failures.push(format!("Series {}: {err}", series.name));
+                    complete = false;
                 }
             }
-            Err(err) => println!("Error {err:?}"),
         }
+        run.series.streams += channels.len();
+        groups.push(Group {
+            category: c,
+            channels: Some(channels),
+            complete,
+        });
     }
+    keep_uncategorized(&mut groups, args, unknown);
+    Some(groups)
+}
 
-    if args.vod {
-        let c_json: Vec<Value>;
-        println!("Getting VOD categories");
-        match reqwest::get(vod_categories_url).await {
-            Ok(resp) => {
-                c_json = resp.json::<Vec<Value>>().await?;
-                println!("Found {} VOD categories", c_json.len());
-                let mut chan_group : ChanGroup = ChanGroup::new(
-                    args.clone(),
-                    "ALL".to_string(),
-                    "movie".to_string(),
-                );
-                if args.single_m3u && args.m3u {
-                    let _ = chan_group.create_file();
-                }
-                for c in &c_json {
-                    match reqwest::get(format!("{}{}", vod_streams_url, c.get_category_id())).await
-                    {
-                        Ok(s_resp) => {
-                            let s_json = s_resp.json::<Vec<Value>>().await?;
-                            println!(
-                                "Found {} streams in {}",
-                                s_json.len(),
-                                c.get_category_name()
-                            );
-                            vod_streams += s_json.len();
-                            if !args.single_m3u {
-                                chan_group = ChanGroup::new(
-                                    args.clone(),
-                                    c.get_category_name().to_string(),
-                                    "movie".to_string(),
-                                );
-                                if args.m3u {
-                                    let _ = chan_group.create_file();
-                                }
-                            }
-
-                        for stream in &s_json {
-                                let _ = chan_group
-                                    .add_channel(c.get_category_name().to_string(),
-                                                 StreamType::Value(stream.clone()));
-                            }
-                            if args.diff {
-                                (vod_inserted, vod_deleted) = match chan_group.make_diff_file() {
-                                    Ok((i, d)) => (vod_inserted + i, vod_deleted + d),
-                                    Err(_) => (vod_inserted, vod_deleted),
-                                };
-                            }
-                        }
-                        Err(err) => println!("Error {err:?}"),
-                    }
-                }
+// Check a type against the safeguard and write its M3U and diff files
+fn update_type(args: &Args, group_type: &str, groups: &[Group], run: &mut Run) {
+    match migrate(&args.m3u_dir(group_type), &args.diff_dir(group_type)) {
+        Ok(moved) if !moved.is_empty() => println!(
+            "Moved {} {group_type} diff files to {:?}",
+            moved.len(),
+            args.diff_dir(group_type)
+        ),
+        Ok(_) => (),
+        Err(e) => println!("Error moving {group_type} diff files: {e}"),
+    }
+    let guard = Safeguard::new(args.diff_dir(group_type), group_type, args.max_deleted);
+    let current = Snapshot::from_groups(groups, guard.previous());
+    if let Err(reason) = guard.check(groups, &current) {
+        if !args.force {
+            println!("Not updating {group_type} files: {reason}");
+            match guard.reject(&current, &reason) {
+                Ok(name) => println!("Rejected channel list saved to {name:?}"),
+                Err(e) => println!("Error saving rejected channel list: {e}"),
             }
-            Err(err) => println!("Error {err:?}"),
-        }
-    }
-    if args.series {
-        println!("Getting Series categories");
-        match reqwest::get(series_categories_url).await {
-            Ok(resp) => {
-                let series_categories_json = match resp.json::<Vec<Value>>().await{
-                    Ok(r) => r,
-                    Err(_) => {
-                        println!("Bad response from the server");
-                        std::process::exit(0);
-                    },
+            run.rejected.push(format!("{group_type}: {reason}"));
+            return;
+        }
+        println!("Updating {group_type} files because of --force: {reason}");
+        run.alerts.push(format!(
+            "{group_type}: {reason} (updated because of --force)"
+        ));
+    }
+
+    if args.m3u {
+        if args.single_m3u {
+            let chan_group = ChanGroup::new(
+                args.m3u_options(),
+                "ALL".to_string(),
+                group_type.to_string(),
+            );
+            let channels = groups.iter().filter_map(|g| g.channels.as_ref()).flatten();
+            write_m3u(chan_group, channels, group_type, run);
+        } else {
+            for g in groups {
+                let Some(channels) = &g.channels else {
+                    continue;
                 };
-                println!("Found {} Series categories", series_categories_json.len());
-                let mut chan_group : ChanGroup = ChanGroup::new(
-                    args.clone(),
-                    "ALL".to_string(),
-                    "series".to_string(),
+                let chan_group = ChanGroup::new(
+                    args.m3u_options(),
+                    g.category.category_name.clone(),
+                    group_type.to_string(),
                 );
-                if args.single_m3u && args.m3u {
-                    let _ = chan_group.create_file();
+                write_m3u(chan_group, channels.iter(), group_type, run);
+            }
+        }
+    }
+
+    if args.diff {
+        let now = run.started.format("%Y%m%d_%H%M%S").to_string();
+        let info = RunInfo {
+            timestamp: run.started.to_rfc3339(),
+            provider: args.server.clone(),
+            group_type: group_type.to_string(),
+        };
+        let stats = run.stats_mut(group_type);
+        match guard.previous() {
+            Some(previous) => {
+                let changes = compare(previous, &current);
+                if changes.is_empty() {
+                    println!("No changes for {group_type}");
+                }
+                let shows = (group_type == "series")
+                    .then(|| SeriesReport::new(previous, &current, &changes));
+                let dir = args.diff_dir(group_type);
+                match write_diff_files(&dir, &changes, shows.as_ref(), args.single_m3u, &now) {
+                    Ok(names) => stats.files.extend(names),
+                    Err(e) => println!("Error saving {group_type} diff files: {e}"),
                 }
-                for c in &series_categories_json {
-                    match reqwest::get(format!("{}{}", series_streams_url, c.get_category_id()))
-                        .await
+                if let Some(format) = args.diff_format {
+                    match write_structured_diff(&dir, &changes, shows.as_ref(), format, &info, &now)
                     {
-                        Ok(series_resp) => {
-                            let series_json = series_resp.json::<Vec<Value>>().await?;
-                            println!(
-                                "Found {} series in {}",
-                                series_json.len(),
-                                c.get_series_name()
-                            );
-                            series_streams += series_json.len();
-                            if !args.single_m3u {
-                                chan_group = ChanGroup::new(
-                                    args.clone(),
-                                    c.get_series_name().to_string(),
-                                    "series".to_string(),
-                                );
-                                if args.m3u {
-                                    let _ = chan_group.create_file();
-                                }
-                            }
-
-                            for series in &series_json {
-                                match reqwest::get(format!("{}{}", series_info_url, series
-                                    .get_series_id())).await {
-                                    Ok(season_resp) => {
-                                        let j: Value = season_resp.json().await?;
-                                        let v = read_series(j.to_string());
-                                        let z = v.get_episodes();
-                                        println!("{} has {} Episodes", v.series_name(), z.len());
-                                        if z.is_empty() {series_no_episodes += 1}
-                                        for a in z.iter() {
-                                            let _ = chan_group.add_channel(v.series_name()
-                                                                               .to_string()
-                                                                               .clone(),
-                                                                           StreamType::Episode(a));
-                                            episodes_inserted += 1;
-                                        }
-                                    }
-                                Err(err) => println!("Error {err:?}"),
-                            }
-                        }
-                        if args.diff {
-                        (series_inserted, series_deleted) = match chan_group.make_diff_file
-                                () {
-                                    Ok((i, d)) => (series_inserted + i, series_deleted + d),
-                                    Err(_) => (series_inserted, series_deleted),
-                                };
-                            }
-                        }
-                        Err(err) => println!("Error {err:?}"),
+                        Ok(name) => stats.files.push(name),
+                        Err(e) => println!("Error saving {group_type} diff: {e}"),
                     }
                 }
+                stats.changes.add(&ChangeCounts::of(&changes));
+                stats.shows = shows;
+                stats.items = changes;
             }
-            Err(err) => println!("Error {err:?}"),
+            None => println!("Not creating diff file since no previous file exists"),
         }
+        match args.retention().prune(&args.diff_dir(group_type)) {
+            Ok(pruned) => {
+                run.pruned_diffs += pruned.removed;
+                run.rolled_up_diffs += pruned.rolled_up;
+            }
+            Err(e) => println!("Error removing old {group_type} diff files: {e}"),
+        }
+    }
+
+    if let Err(e) = guard.save(&current) {
+        println!("Error saving the channel snapshot: {e}");
     }
+
     if args.m3u {
-        if args.live {
-            println!("Live Streams: {live_streams}");
-        }
-        if args.vod {
-            println!("VOD Streams: {vod_streams}");
+        // Files of failed categories are still expected, so they are not treated as stale
+        let produced: BTreeSet<String> = groups
+            .iter()
+            .map(|g| args.m3u_file_name(&g.category.category_name))
+            .collect();
+        match Manifest::update(
+            &args.m3u_dir(group_type),
+            produced,
+            args.stale,
+            args.stale_dry_run,
+        ) {
+            Ok(stale) if !stale.is_empty() => run.stale += stale.len(),
+            Ok(_) => (),
+            Err(e) => println!("Error removing stale {group_type} files: {e}"),
         }
-        if args.series {
-            println!("Series: {series_streams}");
-            println!("Episode Streams: {episodes_inserted}");
-            println!("Series with no episodes: {series_no_episodes}");
+    }
+}
+
+// Write one M3U file. If any write fails the previous file is kept and the category is
+// reported as failed
+fn write_m3u<'a>(
+    mut chan_group: ChanGroup,
+    mut channels: impl Iterator<Item = &'a Channel>,
+    group_type: &str,
+    run: &mut Run,
+) {
+    let written = chan_group
+        .create_file()
+        .and_then(|_| channels.try_for_each(|c| chan_group.add_channel(c)));
+    if let Err(e) = written.and(chan_group.finish()) {
+        println!("Error saving {}: {e}", chan_group.group_name());
+        run.failures.push(format!(
+            "{group_type} category {}: {e}",
+            chan_group.group_name()
+        ));
+    }
+}
+
+fn save_summary(args: &Args, run: &Run, total: ChangeCounts) {
+    match run_summary(args, run, total).save(Path::new(args.diff_base())) {
+        Ok(name) => println!("Run summary saved to {name:?}"),
+        Err(e) => println!("Error saving the run summary: {e}"),
+    }
+}
+
+fn run_summary(args: &Args, run: &Run, total: ChangeCounts) -> RunSummary {
+    let mut summary = RunSummary {
+        timestamp: run.started.to_rfc3339(),
+        provider: args.server.clone(),
+        total,
+        failures: run.failures.clone(),
+        rejected: run.rejected.clone(),
+        ..Default::default()
+    };
+    for (enabled, group_type, stats) in [
+        (args.live, "live", &run.live),
+        (args.vod, "movie", &run.vod),
+        (args.series, "series", &run.series),
+    ] {
+        if enabled {
+            let type_summary = TypeSummary {
+                streams: stats.streams,
+                uncategorized: stats.uncategorized,
+                changes: stats.changes,
+                shows: stats.shows.clone(),
+                files: stats.files.clone(),
+            };
+            summary.types.insert(group_type.to_string(), type_summary);
         }
-        println!("Total Streams: {}", live_streams + vod_streams + episodes_inserted);
     }
+    summary
+}
 
+// Send the alerts and the changes of the run to the configured webhooks. A run with no
+// alerts and no changes is only sent with --notify-always.
+async fn notify(args: &Args, run: &Run, total: ChangeCounts) {
+    let notifier = Notifier::new(args.notify_targets(), Duration::from_secs(args.timeout));
+    if !notifier.is_enabled() {
+        return;
+    }
+    let mut alerts = run.alerts.clone();
+    alerts.extend(
+        run.rejected
+            .iter()
+            .map(|r| format!("Files were not updated for {r}")),
+    );
+    if alerts.is_empty() && total.total() == 0 && !args.notify_always {
+        return;
+    }
+
+    let mut lines = vec![];
+    let streams: Vec<String> = [
+        (args.live, "Live", &run.live),
+        (args.vod, "VOD", &run.vod),
+        (args.series, "Episodes", &run.series),
+    ]
+    .into_iter()
+    .filter(|(enabled, _, _)| *enabled)
+    .map(|(_, label, stats)| format!("{label} {}", stats.streams))
+    .collect();
+    if !streams.is_empty() {
+        lines.push(format!("Streams: {}", streams.join(", ")));
+    }
     if args.diff {
-        if args.live {
-            println!("Live channel changes: Added {live_inserted}, Deleted {live_deleted}");
+        for (enabled, label, stats) in [
+            (args.live, "Live", &run.live),
+            (args.vod, "VOD", &run.vod),
+            (args.series, "Series episodes", &run.series),
+        ] {
+            if enabled {
+                lines.push(format!("{label}: {}", describe_changes(&stats.changes)));
+            }
         }
-        if args.vod {
-            println!("VOD channel changes: Added {vod_inserted}, Deleted {vod_deleted}");
+        if let Some(shows) = &run.series.shows {
+            lines.extend(shows.lines(None));
         }
-        println!(
-            "Total changed: Added {}, Deleted {}",
-            live_inserted + vod_inserted,
-            live_deleted + vod_deleted
-        );
     }
-    Ok(())
+    if !run.failures.is_empty() {
+        lines.push(format!(
+            "{} requests failed after retries",
+            run.failures.len()
+        ));
+    }
+    let title = match alerts.is_empty() {
+        true => format!("xtream2m3u: {} changes on {}", total.total(), args.server),
+        false => format!("xtream2m3u: problems with {}", args.server),
+    };
+    let notification = Notification {
+        title,
+        alerts,
+        lines,
+        errors: run.failures.clone(),
+        summary: args.diff.then(|| run_summary(args, run, total)),
+    };
+    // Keep stdout for the JSON output
+    for outcome in notifier.send(&notification).await {
+        match args.format {
+            OutputFormat::Text => println!("{outcome}"),
+            OutputFormat::Json => eprintln!("{outcome}"),
+        }
+    }
+}
+
+fn save_report(args: &Args, run: &Run) {
+    let sections: Vec<Section> = [
+        (args.live, "Live channels", &run.live),
+        (args.vod, "Movies", &run.vod),
+        (args.series, "Series episodes", &run.series),
+    ]
+    .into_iter()
+    .filter(|(enabled, _, _)| *enabled)
+    .map(|(_, title, stats)| Section {
+        title,
+        changes: &stats.items,
+    })
+    .collect();
+    let dir = Path::new(args.diff_base());
+    let now = run.started.format("%Y%m%d_%H%M%S").to_string();
+    let timestamp = run.started.format("%Y-%m-%d %H:%M:%S").to_string();
+    match write_html_report(dir, &timestamp, &now, &sections) {
+        Ok(name) => println!("Change report saved to {name:?}"),
+        Err(e) => println!("Error saving the change report: {e}"),
+    }
+    if let Err(e) = args.retention().prune(dir) {
+        println!("Error removing old change reports: {e}");
+    }
+}
+
+fn save_feed(args: &Args, run: &Run) {
+    let dir = Path::new(&args.output_dir);
+    let mut feed = Feed::load(dir);
+    feed.add(&run.vod.items, "movie", &args.server, run.started);
+    feed.add(&run.series.items, "series", &args.server, run.started);
+    match feed.save(dir, &args.server, args.feed_entries, run.started) {
+        Ok(name) => println!("Feed of new content saved to {name:?}"),
+        Err(e) => println!("Error saving the feed: {e}"),
+    }
+}
+
+fn describe_changes(c: &ChangeCounts) -> String {
+    format!(
+        "Added {}, Deleted {}, Renamed {}, Moved {}, Changed {}",
+        c.added, c.removed, c.renamed, c.moved, c.metadata
+    )
+}
+
+fn report_fallback<T>(fetched: &CategoryStreams<T>) {
+    if let Some(reason) = &fetched.fallback {
+        println!("{reason}, using one request per category");
+    }
+    if let Some(reason) = &fetched.unknown {
+        println!("{reason}, streams with no known category are not known this run");
+    }
+}
+
+// When the streams with no known category could not be looked up, the ones found by earlier
+// runs are kept as if their category had failed, without reporting a failure. A server with
+// no categories at all is left for the safeguard.
+fn keep_uncategorized(groups: &mut Vec<Group>, args: &Args, unknown: bool) {
+    if unknown && !groups.is_empty() {
+        groups.push(Group::unknown(Category::uncategorized(
+            &args.uncategorized_name,
+        )));
+    }
+}
+
+fn report_skipped(skipped: &[Skipped]) -> usize {
+    for s in skipped {
+        println!("Skipping {}: {}", s.name, s.error);
+    }
+    skipped.len()
 }
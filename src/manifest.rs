@@ -0,0 +1,92 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::fs::{create_dir_all, read_to_string, remove_file, rename};
+use std::path::{Path, PathBuf};
+
+use crate::atomic::write_atomic;
+
+const MANIFEST: &str = ".xtream2m3u_manifest.json";
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StaleAction {
+    Keep,
+    Delete,
+    Archive,
+}
+
+// The M3U files produced in an output directory, so files for categories that have
+// disappeared can be found on the next run. Files that were there before are never touched.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Manifest {
+    pub files: BTreeSet<String>,
+}
+
+impl Manifest {
+    pub fn load(dir: &Path) -> Manifest {
+        read_to_string(dir.join(MANIFEST))
+            .ok()
+            .and_then(|s| serde_json::from_str(&s).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self, dir: &Path) -> std::io::Result<()> {
+        let contents = serde_json::to_string_pretty(self).map_err(std::io::Error::other)?;
+        write_atomic(&dir.join(MANIFEST), &contents, 0)
+    }
+
+    // Deal with the files of the previous manifest that were not produced this time, and
+    // save the new manifest. Stale files that are kept stay in the manifest so they can still
+    // be removed by a later run.
+    pub fn update(
+        dir: &Path,
+        mut produced: BTreeSet<String>,
+        action: StaleAction,
+        dry_run: bool,
+    ) -> std::io::Result<Vec<String>> {
+        let previous = Manifest::load(dir);
+        let stale: Vec<String> = previous
+            .files
+            .into_iter()
+            .filter(|f| !produced.contains(f) && dir.join(f).exists())
+            .collect();
+
+        for f in &stale {
+            match (action, dry_run) {
+                (StaleAction::Keep, _) => println!("Stale file {:?}", dir.join(f)),
+                (StaleAction::Delete, true) => println!("Would delete {:?}", dir.join(f)),
+                (StaleAction::Archive, true) => println!("Would archive {:?}", dir.join(f)),
+                (StaleAction::Delete, false) => {
+                    println!("Deleting {:?}", dir.join(f));
+                    for path in with_backups(dir, f) {
+                        remove_file(path)?;
+                    }
+                }
+                (StaleAction::Archive, false) => {
+                    let archive = dir.join("archive");
+                    create_dir_all(&archive)?;
+                    println!("Moving {:?} to {archive:?}", dir.join(f));
+                    for path in with_backups(dir, f) {
+                        rename(&path, archive.join(path.file_name().unwrap_or_default()))?;
+                    }
+                }
+            }
+        }
+        if action == StaleAction::Keep || dry_run {
+            produced.extend(stale.iter().cloned());
+        }
+        Manifest { files: produced }.save(dir)?;
+        Ok(stale)
+    }
+}
+
+// A file and the backups kept of it
+fn with_backups(dir: &Path, name: &str) -> Vec<PathBuf> {
+    let mut paths = vec![dir.join(name)];
+    let mut n = 1;
+    while dir.join(format!("{name}.{n}")).exists() {
+        paths.push(dir.join(format!("{name}.{n}")));
+        n += 1;
+    }
+    paths
+}
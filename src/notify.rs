@@ -0,0 +1,223 @@
+use lettre::message::header::ContentType;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::time::Duration;
+
+use crate::diff::RunSummary;
+
+// What a run has to report. Alerts are problems that need attention, lines are the
+// summary of the changes.
+#[derive(Serialize, Debug, Default)]
+pub struct Notification {
+    pub title: String,
+    pub alerts: Vec<String>,
+    pub lines: Vec<String>,
+    pub errors: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub summary: Option<RunSummary>,
+}
+
+impl Notification {
+    pub fn is_alert(&self) -> bool {
+        !self.alerts.is_empty()
+    }
+
+    // Alerts first, then the summary, as plain text
+    pub fn message(&self) -> String {
+        self.alerts
+            .iter()
+            .chain(self.lines.iter())
+            .cloned()
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpSecurity {
+    // TLS from the start, usually on port 465
+    Tls,
+    // Plain connection upgraded with STARTTLS, usually on port 587
+    Starttls,
+    // No encryption, only for a local relay
+    None,
+}
+
+// The mail server and addresses of email notifications. Without a port the usual one for
+// the security is used, without a user there is no authentication.
+#[derive(Debug, Clone)]
+pub struct Email {
+    pub host: String,
+    pub port: Option<u16>,
+    pub security: SmtpSecurity,
+    pub user: Option<String>,
+    pub password: Option<String>,
+    pub from: String,
+    pub to: Vec<String>,
+}
+
+impl Email {
+    fn message(&self, n: &Notification) -> Result<Message, String> {
+        let mut builder = Message::builder()
+            .from(
+                self.from
+                    .parse()
+                    .map_err(|e| format!("bad sender {:?}: {e}", self.from))?,
+            )
+            .subject(&n.title)
+            .header(ContentType::TEXT_PLAIN);
+        for to in &self.to {
+            builder = builder.to(to
+                .parse()
+                .map_err(|e| format!("bad recipient {to:?}: {e}"))?);
+        }
+        let mut body = n.message();
+        if !n.errors.is_empty() {
+            body += "\n\nErrors:\n";
+            body += &n.errors.join("\n");
+        }
+        builder.body(body + "\n").map_err(|e| e.to_string())
+    }
+
+    async fn send(&self, n: &Notification, timeout: Duration) -> Result<(), String> {
+        let message = self.message(n)?;
+        let mut transport = match self.security {
+            SmtpSecurity::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(&self.host),
+            SmtpSecurity::Starttls => {
+                AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&self.host)
+            }
+            SmtpSecurity::None => Ok(AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(
+                &self.host,
+            )),
+        }
+        .map_err(|e| e.to_string())?
+        .timeout(Some(timeout));
+        if let Some(port) = self.port {
+            transport = transport.port(port);
+        }
+        if let Some(user) = &self.user {
+            let password = self.password.clone().unwrap_or_default();
+            transport = transport.credentials(Credentials::new(user.clone(), password));
+        }
+        transport
+            .build()
+            .send(message)
+            .await
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    }
+}
+
+// Where notifications go
+#[derive(Debug, Clone, Default)]
+pub struct Targets {
+    // URLs that get a JSON summary
+    pub webhooks: Vec<String>,
+    // ntfy topic URLs
+    pub ntfy: Vec<String>,
+    // Gotify message URLs, with ?token=
+    pub gotify: Vec<String>,
+    pub email: Option<Email>,
+}
+
+impl Targets {
+    pub fn is_empty(&self) -> bool {
+        self.webhooks.is_empty()
+            && self.ntfy.is_empty()
+            && self.gotify.is_empty()
+            && self.email.is_none()
+    }
+}
+
+// Sends notifications to the targets. Failures are printed but never stop the run.
+#[derive(Debug, Clone)]
+pub struct Notifier {
+    http: reqwest::Client,
+    timeout: Duration,
+    targets: Targets,
+}
+
+impl Notifier {
+    pub fn new(targets: Targets, timeout: Duration) -> Notifier {
+        Notifier {
+            http: reqwest::Client::new(),
+            timeout,
+            targets,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.targets.is_empty()
+    }
+
+    // Send to every target, returning a line for each about how it went
+    pub async fn send(&self, n: &Notification) -> Vec<String> {
+        let mut outcomes = vec![];
+        let message = n.message();
+        for url in &self.targets.webhooks {
+            let request = self.http.post(url).json(&json!({
+                "title": n.title,
+                "message": message,
+                "alert": n.is_alert(),
+                "alerts": n.alerts,
+                "summary": n.summary,
+            }));
+            outcomes.push(self.post(request, "webhook", url).await);
+        }
+        // https://docs.ntfy.sh/publish/
+        for url in &self.targets.ntfy {
+            let (priority, tags) = match n.is_alert() {
+                true => ("high", "warning"),
+                false => ("default", "tv"),
+            };
+            let request = self
+                .http
+                .post(url)
+                .header("Title", &n.title)
+                .header("Priority", priority)
+                .header("Tags", tags)
+                .body(message.clone());
+            outcomes.push(self.post(request, "ntfy", url).await);
+        }
+        // https://gotify.net/docs/pushmsg
+        for url in &self.targets.gotify {
+            let request = self.http.post(url).json(&json!({
+                "title": n.title,
+                "message": message,
+                "priority": if n.is_alert() { 8 } else { 4 },
+            }));
+            outcomes.push(self.post(request, "Gotify", url).await);
+        }
+        if let Some(email) = &self.targets.email {
+            outcomes.push(match email.send(n, self.timeout).await {
+                Ok(()) => "Sent email notification".to_string(),
+                Err(e) => format!("Error sending email notification: {e}"),
+            });
+        }
+        outcomes
+    }
+
+    async fn post(&self, request: reqwest::RequestBuilder, kind: &str, url: &str) -> String {
+        match request.timeout(self.timeout).send().await {
+            Ok(resp) if resp.status().is_success() => format!("Sent {kind} notification"),
+            Ok(resp) => format!(
+                "Error sending {kind} notification to {}: {}",
+                redact(url),
+                resp.status()
+            ),
+            Err(e) => format!(
+                "Error sending {kind} notification to {}: {}",
+                redact(url),
+                e.without_url()
+            ),
+        }
+    }
+}
+
+// Webhook URLs often carry a token in the query, which should not end up in logs
+fn redact(url: &str) -> &str {
+    url.split('?').next().unwrap_or(url)
+}
@@ -0,0 +1,134 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::fs::create_dir_all;
+use std::path::{Path, PathBuf};
+
+use crate::atomic::write_atomic;
+use crate::diff::{Change, ChangeCounts, ChangeKind};
+
+const STYLE: &str = "
+body { font-family: sans-serif; margin: 2em; color: #222; }
+table.counts { border-collapse: collapse; margin-bottom: 2em; }
+table.counts td, table.counts th { border: 1px solid #ccc; padding: 0.3em 0.8em; text-align: right; }
+table.counts th:first-child, table.counts td:first-child { text-align: left; }
+h3 { margin-bottom: 0.3em; }
+ul { list-style: none; padding-left: 0; margin-top: 0; }
+li { padding: 0.2em 0; display: flex; align-items: center; gap: 0.5em; }
+li img { width: 48px; height: 32px; object-fit: contain; }
+li .logo { width: 48px; }
+.added { color: #1a7f37; }
+.removed { color: #cf222e; }
+.changed { color: #9a6700; }
+";
+
+// The changes of one type of stream, as shown in the report
+pub struct Section<'a> {
+    pub title: &'a str,
+    pub changes: &'a [Change],
+}
+
+// Escape text for HTML and XML
+pub fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+fn item(html: &mut String, c: &Change) {
+    let class = match c.kind {
+        ChangeKind::Added => "added",
+        ChangeKind::Removed => "removed",
+        _ => "changed",
+    };
+    let logo = match c.channel.logo.is_empty() {
+        true => "<span class=\"logo\"></span>".to_string(),
+        false => format!(
+            "<img src=\"{}\" alt=\"\" loading=\"lazy\">",
+            escape(&c.channel.logo)
+        ),
+    };
+    let series = match &c.channel.episode {
+        Some(e) => format!(" <small>({})</small>", escape(&e.series_name)),
+        None => "".to_string(),
+    };
+    let _ = writeln!(
+        html,
+        "<li class=\"{class}\">{logo}<span>{}{series}</span></li>",
+        escape(&c.describe())
+    );
+}
+
+fn section(html: &mut String, s: &Section) {
+    let _ = writeln!(html, "<h2>{}</h2>", escape(s.title));
+    if s.changes.is_empty() {
+        let _ = writeln!(html, "<p>No changes</p>");
+        return;
+    }
+    let mut by_category: BTreeMap<&str, Vec<&Change>> = BTreeMap::new();
+    for c in s.changes {
+        by_category.entry(&c.channel.category).or_default().push(c);
+    }
+    for (category, mut changes) in by_category {
+        changes.sort_by(|a, b| (a.kind, &a.channel.name).cmp(&(b.kind, &b.channel.name)));
+        let _ = writeln!(html, "<h3>{}</h3>\n<ul>", escape(category));
+        for c in changes {
+            item(html, c);
+        }
+        let _ = writeln!(html, "</ul>");
+    }
+}
+
+// Write a single HTML file with every change of the run. Logos are loaded from the
+// provider, everything else is in the file.
+pub fn write_html_report(
+    dir: &Path,
+    timestamp: &str,
+    now: &str,
+    sections: &[Section],
+) -> std::io::Result<PathBuf> {
+    let mut html = String::new();
+    let _ = writeln!(
+        html,
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+         <title>Changes {0}</title>\n<style>{STYLE}</style>\n</head>\n<body>\n\
+         <h1>Changes {0}</h1>",
+        escape(timestamp)
+    );
+    let _ = writeln!(
+        html,
+        "<table class=\"counts\">\n<tr><th></th><th>Added</th><th>Removed</th>\
+         <th>Renamed</th><th>Moved</th><th>Changed</th></tr>"
+    );
+    for s in sections {
+        let c = ChangeCounts::of(s.changes);
+        let _ = writeln!(
+            html,
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+            escape(s.title),
+            c.added,
+            c.removed,
+            c.renamed,
+            c.moved,
+            c.metadata
+        );
+    }
+    let _ = writeln!(html, "</table>");
+    for s in sections {
+        section(&mut html, s);
+    }
+    let _ = writeln!(html, "</body>\n</html>");
+
+    create_dir_all(dir)?;
+    let name = dir.join(format!("report_{now}.html"));
+    write_atomic(&name, &html, 0)?;
+    Ok(name)
+}
@@ -0,0 +1,313 @@
+use chrono::{Local, NaiveDateTime, TimeDelta};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::fs::{read_dir, read_to_string, remove_file};
+use std::path::{Path, PathBuf};
+
+use crate::atomic::write_atomic;
+
+const STAMP: &str = "%Y%m%d_%H%M%S";
+
+// How long diff files are kept. A run is every diff file with the same timestamp, so the
+// per-category files of one run are always kept or removed together.
+#[derive(Debug, Clone, Copy)]
+pub struct Retention {
+    pub keep_runs: usize,
+    pub max_age_days: u64,
+    pub rollup: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct Pruned {
+    pub removed: usize,
+    pub rolled_up: usize,
+}
+
+// A diff file and the run it belongs to
+#[derive(Debug)]
+struct DiffFile {
+    path: PathBuf,
+    group: String,
+    ext: String,
+}
+
+// {group}_diff_{YYYYmmdd_HHMMSS}.{txt,json,ndjson} or report_{YYYYmmdd_HHMMSS}.html
+fn parse_name(name: &str) -> Option<(NaiveDateTime, String, String)> {
+    let (stem, ext) = name.rsplit_once('.')?;
+    if !["txt", "json", "ndjson", "html"].contains(&ext) || stem.len() < 15 {
+        return None;
+    }
+    let (rest, stamp) = stem.split_at_checked(stem.len() - 15)?;
+    let group = match (rest, ext) {
+        ("report_", "html") => "report",
+        _ => rest.strip_suffix("_diff_")?,
+    };
+    let time = NaiveDateTime::parse_from_str(stamp, STAMP).ok()?;
+    Some((time, group.to_string(), ext.to_string()))
+}
+
+impl Retention {
+    pub fn is_enabled(&self) -> bool {
+        self.keep_runs > 0 || self.max_age_days > 0
+    }
+
+    fn runs(dir: &Path) -> std::io::Result<BTreeMap<NaiveDateTime, Vec<DiffFile>>> {
+        let mut runs: BTreeMap<NaiveDateTime, Vec<DiffFile>> = BTreeMap::new();
+        for entry in read_dir(dir)?.flatten() {
+            let name = entry.file_name().to_string_lossy().to_string();
+            if let Some((time, group, ext)) = parse_name(&name) {
+                runs.entry(time).or_default().push(DiffFile {
+                    path: entry.path(),
+                    group,
+                    ext,
+                });
+            }
+        }
+        Ok(runs)
+    }
+
+    // Remove the diff files of runs that are too old or beyond the newest keep_runs. With
+    // rollup the text diffs are first added to changelog_{YYYY-MM}.txt.
+    pub fn prune(&self, dir: &Path) -> std::io::Result<Pruned> {
+        let mut pruned = Pruned::default();
+        if !self.is_enabled() || !dir.is_dir() {
+            return Ok(pruned);
+        }
+        let runs = Self::runs(dir)?;
+        let cutoff = (self.max_age_days > 0)
+            .then(|| Local::now().naive_local() - TimeDelta::days(self.max_age_days as i64));
+        let keep_from = match self.keep_runs {
+            0 => 0,
+            n => runs.len().saturating_sub(n),
+        };
+
+        let mut changelogs: BTreeMap<String, String> = BTreeMap::new();
+        let mut old = vec![];
+        for (i, (time, mut files)) in runs.into_iter().enumerate() {
+            if i >= keep_from && cutoff.is_none_or(|c| time >= c) {
+                continue;
+            }
+            files.sort_by(|a, b| a.group.cmp(&b.group));
+            for f in files {
+                if self.rollup && f.ext == "txt" {
+                    let log = changelogs
+                        .entry(time.format("%Y-%m").to_string())
+                        .or_default();
+                    let _ = writeln!(log, "## {} {}", time.format("%Y-%m-%d %H:%M:%S"), f.group);
+                    let _ = writeln!(log, "{}", read_to_string(&f.path)?.trim_end());
+                    let _ = writeln!(log);
+                    pruned.rolled_up += 1;
+                }
+                old.push(f.path);
+            }
+        }
+
+        // Changelogs are saved before anything is removed, so no change is ever lost
+        for (month, entries) in changelogs {
+            let name = dir.join(format!("changelog_{month}.txt"));
+            let contents = read_to_string(&name).unwrap_or_default() + &entries;
+            write_atomic(&name, &contents, 0)?;
+        }
+        for path in old {
+            remove_file(path)?;
+            pruned.removed += 1;
+        }
+        Ok(pruned)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::{create_dir_all, remove_dir_all, write};
+
+    // An empty directory for one test, removed when it is dropped
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> TempDir {
+            let dir = std::env::temp_dir().join(format!(
+                "xtream2m3u-retention-{name}-{}",
+                std::process::id()
+            ));
+            let _ = remove_dir_all(&dir);
+            create_dir_all(&dir).unwrap();
+            TempDir(dir)
+        }
+
+        fn add(&self, name: &str, contents: &str) {
+            write(self.0.join(name), contents).unwrap();
+        }
+
+        fn files(&self) -> Vec<String> {
+            let mut names: Vec<String> = read_dir(&self.0)
+                .unwrap()
+                .flatten()
+                .map(|e| e.file_name().to_string_lossy().to_string())
+                .collect();
+            names.sort();
+            names
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = remove_dir_all(&self.0);
+        }
+    }
+
+    fn retention(keep_runs: usize, max_age_days: u64, rollup: bool) -> Retention {
+        Retention {
+            keep_runs,
+            max_age_days,
+            rollup,
+        }
+    }
+
+    fn stamp(days_ago: i64) -> String {
+        (Local::now().naive_local() - TimeDelta::days(days_ago))
+            .format(STAMP)
+            .to_string()
+    }
+
+    #[test]
+    fn names() {
+        let time = |s| NaiveDateTime::parse_from_str(s, STAMP).unwrap();
+        assert_eq!(
+            parse_name("News_diff_20260101_120000.txt"),
+            Some((time("20260101_120000"), "News".into(), "txt".into()))
+        );
+        assert_eq!(
+            parse_name("Kids_diff_Extra_diff_20260101_120000.ndjson"),
+            Some((
+                time("20260101_120000"),
+                "Kids_diff_Extra".into(),
+                "ndjson".into()
+            ))
+        );
+        assert_eq!(
+            parse_name("live_diff_20260101_120000.json"),
+            Some((time("20260101_120000"), "live".into(), "json".into()))
+        );
+        assert_eq!(
+            parse_name("report_20260101_120000.html"),
+            Some((time("20260101_120000"), "report".into(), "html".into()))
+        );
+        assert_eq!(parse_name("News_diff_20260101_120000.m3u"), None);
+        assert_eq!(parse_name("News_20260101_120000.txt"), None);
+        assert_eq!(parse_name("News_diff_20261301_120000.txt"), None);
+        assert_eq!(parse_name("report_20260101_120000.txt"), None);
+        assert_eq!(parse_name("changelog_2026-01.txt"), None);
+        assert_eq!(parse_name("snapshot.json"), None);
+        assert_eq!(parse_name(".txt"), None);
+    }
+
+    #[test]
+    fn disabled() {
+        let dir = TempDir::new("disabled");
+        dir.add("News_diff_20200101_120000.txt", "+ One\n");
+        let pruned = retention(0, 0, true).prune(&dir.0).unwrap();
+        assert_eq!((pruned.removed, pruned.rolled_up), (0, 0));
+        assert_eq!(dir.files().len(), 1);
+    }
+
+    #[test]
+    fn keep_runs() {
+        let dir = TempDir::new("keep");
+        for stamp in ["20260101_120000", "20260102_120000", "20260103_120000"] {
+            dir.add(&format!("News_diff_{stamp}.txt"), "+ One\n");
+            dir.add(&format!("Sports_diff_{stamp}.txt"), "+ Two\n");
+        }
+        dir.add("snapshot.json", "{}");
+        dir.add("notes.txt", "");
+        let pruned = retention(2, 0, false).prune(&dir.0).unwrap();
+        assert_eq!((pruned.removed, pruned.rolled_up), (2, 0));
+        // Both files of the oldest run went, everything that is not a diff file stays
+        assert_eq!(
+            dir.files(),
+            [
+                "News_diff_20260102_120000.txt",
+                "News_diff_20260103_120000.txt",
+                "Sports_diff_20260102_120000.txt",
+                "Sports_diff_20260103_120000.txt",
+                "notes.txt",
+                "snapshot.json",
+            ]
+        );
+    }
+
+    #[test]
+    fn max_age() {
+        let dir = TempDir::new("age");
+        let (old, new) = (stamp(40), stamp(2));
+        dir.add(&format!("live_diff_{old}.json"), "{}");
+        dir.add(&format!("News_diff_{old}.txt"), "+ One\n");
+        dir.add(&format!("report_{old}.html"), "");
+        dir.add(&format!("News_diff_{new}.txt"), "+ Two\n");
+        let pruned = retention(0, 30, false).prune(&dir.0).unwrap();
+        assert_eq!(pruned.removed, 3);
+        assert_eq!(dir.files(), [format!("News_diff_{new}.txt")]);
+    }
+
+    #[test]
+    fn keep_runs_and_max_age() {
+        // A run goes when either limit says so
+        let dir = TempDir::new("both");
+        let stamps: Vec<String> = [50, 40, 3, 2, 1].into_iter().map(stamp).collect();
+        for s in &stamps {
+            dir.add(&format!("News_diff_{s}.txt"), "");
+        }
+        let pruned = retention(4, 30, false).prune(&dir.0).unwrap();
+        assert_eq!(pruned.removed, 2);
+        assert_eq!(dir.files().len(), 3);
+
+        let pruned = retention(2, 30, false).prune(&dir.0).unwrap();
+        assert_eq!(pruned.removed, 1);
+        assert_eq!(
+            dir.files(),
+            [
+                format!("News_diff_{}.txt", stamps[3]),
+                format!("News_diff_{}.txt", stamps[4]),
+            ]
+        );
+    }
+
+    #[test]
+    fn rollup() {
+        let dir = TempDir::new("rollup");
+        dir.add("changelog_2026-01.txt", "## earlier\n\n");
+        dir.add("Sports_diff_20260105_080000.txt", "- Two\n");
+        dir.add("News_diff_20260105_080000.txt", "+ One\n");
+        dir.add("live_diff_20260105_080000.json", "{}");
+        dir.add("News_diff_20260201_080000.txt", "~ One -> One HD\n");
+        dir.add("News_diff_20260301_080000.txt", "+ Three\n");
+        let pruned = retention(1, 0, true).prune(&dir.0).unwrap();
+        assert_eq!((pruned.removed, pruned.rolled_up), (4, 3));
+        assert_eq!(
+            dir.files(),
+            [
+                "News_diff_20260301_080000.txt",
+                "changelog_2026-01.txt",
+                "changelog_2026-02.txt",
+            ]
+        );
+        // Added to the end of an existing changelog, one section per category in name order
+        assert_eq!(
+            read_to_string(dir.0.join("changelog_2026-01.txt")).unwrap(),
+            "## earlier\n\n\
+             ## 2026-01-05 08:00:00 News\n+ One\n\n\
+             ## 2026-01-05 08:00:00 Sports\n- Two\n\n"
+        );
+        assert_eq!(
+            read_to_string(dir.0.join("changelog_2026-02.txt")).unwrap(),
+            "## 2026-02-01 08:00:00 News\n~ One -> One HD\n\n"
+        );
+    }
+
+    #[test]
+    fn missing_dir() {
+        let dir = std::env::temp_dir().join("xtream2m3u-retention-missing");
+        let pruned = retention(1, 1, true).prune(&dir).unwrap();
+        assert_eq!(pruned.removed, 0);
+    }
+}
@@ -0,0 +1,67 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::Duration;
+
+// How provider requests are timed out and retried
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    pub timeout: Duration,
+    pub retries: u32,
+    pub backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            timeout: Duration::from_secs(30),
+            retries: 3,
+            backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(30),
+        }
+    }
+}
+
+impl RetryPolicy {
+    // Exponential backoff with jitter, capped at max_backoff. A longer wait asked for by the
+    // server with Retry-After is always honored.
+    pub fn delay(&self, attempt: u32, retry_after: Option<Duration>) -> Duration {
+        let exp = self
+            .backoff
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(self.max_backoff);
+        let half = exp / 2;
+        let jitter = match half.as_millis() as u64 {
+            0 => Duration::ZERO,
+            ms => Duration::from_millis(random() % ms),
+        };
+        (half + jitter).max(retry_after.unwrap_or_default())
+    }
+}
+
+fn random() -> u64 {
+    let mut h = RandomState::new().build_hasher();
+    h.write_u64(
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos() as u64,
+    );
+    h.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retry_after_is_not_capped() {
+        let policy = RetryPolicy {
+            max_backoff: Duration::from_secs(30),
+            ..RetryPolicy::default()
+        };
+        let retry_after = Duration::from_secs(120);
+        assert_eq!(policy.delay(0, Some(retry_after)), retry_after);
+        assert!(policy.delay(10, None) <= policy.max_backoff);
+    }
+}
@@ -0,0 +1,82 @@
+use serde_json::json;
+use static_str_ops::static_format;
+use std::fs::create_dir_all;
+use std::path::PathBuf;
+
+use crate::atomic::write_atomic;
+use crate::diff::Snapshot;
+use crate::fetch::Group;
+
+// Exit status when the files of at least one type were not updated
+pub const EXIT_REJECTED: i32 = 3;
+
+// Refuses to replace the files of a type when a run looks broken, compared to the channels
+// saved by the last accepted run
+#[derive(Debug)]
+pub struct Safeguard {
+    dir: PathBuf,
+    group_type: String,
+    max_deleted: f64,
+    previous: Option<Snapshot>,
+}
+
+impl Safeguard {
+    // dir is the diff directory of the type, max_deleted the percentage of channels a run
+    // may remove
+    pub fn new(dir: PathBuf, group_type: &str, max_deleted: f64) -> Safeguard {
+        let previous = Snapshot::load(&dir);
+        Safeguard {
+            dir,
+            group_type: group_type.to_string(),
+            max_deleted,
+            previous,
+        }
+    }
+
+    // The channels saved by the last accepted run
+    pub fn previous(&self) -> Option<&Snapshot> {
+        self.previous.as_ref()
+    }
+
+    pub fn check(&self, groups: &[Group], current: &Snapshot) -> Result<(), String> {
+        let Some(previous) = self.previous.as_ref().filter(|p| !p.is_empty()) else {
+            return Ok(());
+        };
+        if groups.is_empty() {
+            return Err(format!(
+                "the server returned no {} categories, the last run had {} channels",
+                self.group_type,
+                previous.len()
+            ));
+        }
+        let vanished = previous.vanished(current);
+        let pct = vanished as f64 * 100.0 / previous.len() as f64;
+        if pct > self.max_deleted {
+            return Err(format!(
+                "{vanished} of {} {} channels ({pct:.1}%) would be removed, more than the {}% allowed",
+                previous.len(),
+                self.group_type,
+                self.max_deleted
+            ));
+        }
+        Ok(())
+    }
+
+    // Keep the channels of a rejected run for inspection, without touching the snapshot
+    pub fn reject(&self, current: &Snapshot, reason: &str) -> std::io::Result<PathBuf> {
+        create_dir_all(&self.dir)?;
+        let now = chrono::offset::Local::now()
+            .format("%Y%m%d_%H%M%S")
+            .to_string();
+        let name = self.dir.join(static_format!("rejected_{now}.json"));
+        let contents = json!({"reason": reason, "channels": current.channels});
+        write_atomic(&name, &contents.to_string(), 0)?;
+        Ok(name)
+    }
+
+    // Channels of failed categories are carried forward in current, so the next check
+    // still compares against a full list
+    pub fn save(&self, current: &Snapshot) -> std::io::Result<()> {
+        current.save(&Snapshot::path(&self.dir))
+    }
+}
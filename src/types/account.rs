@@ -0,0 +1,170 @@
+use chrono::DateTime;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::types::de;
+
+// Exit status when --warn-days is given and the account expires soon or is not Active
+pub const EXIT_EXPIRING: i32 = 4;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct AccountInfo {
+    #[serde(default)]
+    pub user_info: UserInfo,
+    #[serde(default)]
+    pub server_info: ServerInfo,
+}
+
+// Fields the server leaves out stay out, rather than showing up as empty values
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct UserInfo {
+    #[serde(
+        default,
+        deserialize_with = "de::opt_string_or_number",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub username: Option<String>,
+    #[serde(
+        default,
+        deserialize_with = "de::opt_string_or_number",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub message: Option<String>,
+    #[serde(
+        default,
+        deserialize_with = "de::opt_bool_or_string",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub auth: Option<bool>,
+    #[serde(
+        default,
+        deserialize_with = "de::opt_string_or_number",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub status: Option<String>,
+    #[serde(
+        default,
+        deserialize_with = "de::opt_int_or_string",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub exp_date: Option<i64>,
+    #[serde(
+        default,
+        deserialize_with = "de::opt_bool_or_string",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub is_trial: Option<bool>,
+    #[serde(
+        default,
+        deserialize_with = "de::opt_int_or_string",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub active_cons: Option<i64>,
+    #[serde(
+        default,
+        deserialize_with = "de::opt_int_or_string",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub created_at: Option<i64>,
+    #[serde(
+        default,
+        deserialize_with = "de::opt_int_or_string",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub max_connections: Option<i64>,
+    #[serde(
+        default,
+        deserialize_with = "de::string_list",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub allowed_output_formats: Vec<String>,
+    // Fields not listed above, such as "process", kept as the server sent them
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct ServerInfo {
+    #[serde(
+        default,
+        deserialize_with = "de::opt_string_or_number",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub url: Option<String>,
+    #[serde(
+        default,
+        deserialize_with = "de::opt_string_or_number",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub port: Option<String>,
+    #[serde(
+        default,
+        deserialize_with = "de::opt_string_or_number",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub https_port: Option<String>,
+    #[serde(
+        default,
+        deserialize_with = "de::opt_string_or_number",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub server_protocol: Option<String>,
+    #[serde(
+        default,
+        deserialize_with = "de::opt_string_or_number",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub rtmp_port: Option<String>,
+    #[serde(
+        default,
+        deserialize_with = "de::opt_string_or_number",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub timezone: Option<String>,
+    #[serde(
+        default,
+        deserialize_with = "de::opt_int_or_string",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub timestamp_now: Option<i64>,
+    #[serde(
+        default,
+        deserialize_with = "de::opt_string_or_number",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub time_now: Option<String>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+fn format_ts(ts: Option<i64>) -> String {
+    DateTime::from_timestamp(ts.unwrap_or_default(), 0)
+        .unwrap_or_default()
+        .to_string()
+}
+
+impl UserInfo {
+    pub fn expires(&self) -> String {
+        format_ts(self.exp_date)
+    }
+
+    pub fn created(&self) -> String {
+        format_ts(self.created_at)
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.status
+            .as_deref()
+            .is_some_and(|s| s.eq_ignore_ascii_case("active"))
+    }
+
+    // Whole days until the account expires, None for accounts that never expire
+    pub fn days_left(&self) -> Option<i64> {
+        let exp = self.exp_date.filter(|e| *e > 0)?;
+        Some((exp - chrono::Utc::now().timestamp()).div_euclid(86400))
+    }
+
+    pub fn expires_within(&self, days: i64) -> bool {
+        self.days_left().is_some_and(|left| left <= days)
+    }
+}
@@ -0,0 +1,24 @@
+use serde::{Deserialize, Serialize};
+
+use crate::types::de;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Category {
+    #[serde(deserialize_with = "de::id")]
+    pub category_id: String,
+    #[serde(default, deserialize_with = "de::string_or_number")]
+    pub category_name: String,
+    #[serde(default, deserialize_with = "de::opt_int_or_string")]
+    pub parent_id: Option<i64>,
+}
+
+impl Category {
+    // Stands in for streams that have no category, or one the server did not list
+    pub fn uncategorized(name: &str) -> Category {
+        Category {
+            category_id: String::new(),
+            category_name: name.to_string(),
+            parent_id: None,
+        }
+    }
+}
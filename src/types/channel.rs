@@ -0,0 +1,92 @@
+use serde::{Deserialize, Serialize};
+
+use crate::types::series::{Episode, EpisodeTrait};
+use crate::types::{LiveStream, SeriesStream, VodStream};
+
+// A single playlist entry, whatever type of stream it came from
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Channel {
+    pub stream_id: String,
+    pub name: String,
+    pub category: String,
+    #[serde(default)]
+    pub epg_id: String,
+    #[serde(default)]
+    pub logo: String,
+    #[serde(default)]
+    pub ext: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub added: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub episode: Option<EpisodeInfo>,
+}
+
+// The series an episode belongs to
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct EpisodeInfo {
+    pub series_id: String,
+    pub series_name: String,
+    #[serde(default)]
+    pub season: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub cover: String,
+}
+
+impl Channel {
+    pub fn from_live(stream: &LiveStream, category: &str) -> Channel {
+        Channel {
+            stream_id: stream.stream_id.clone(),
+            name: stream.name.clone(),
+            category: category.to_string(),
+            epg_id: stream.epg_channel_id.clone(),
+            logo: stream.stream_icon.clone(),
+            ext: "".to_string(),
+            added: stream.added.clone(),
+            episode: None,
+        }
+    }
+
+    pub fn from_vod(stream: &VodStream, category: &str) -> Channel {
+        Channel {
+            stream_id: stream.stream_id.clone(),
+            name: stream.name.clone(),
+            category: category.to_string(),
+            epg_id: "".to_string(),
+            logo: stream.stream_icon.clone(),
+            ext: stream.ext(),
+            added: stream.added.clone(),
+            episode: None,
+        }
+    }
+
+    pub fn from_episode(
+        episode: &Episode,
+        series: &SeriesStream,
+        series_name: &str,
+        category: &str,
+    ) -> Channel {
+        Channel {
+            stream_id: episode.id.clone(),
+            name: episode.title.clone(),
+            category: category.to_string(),
+            epg_id: "".to_string(),
+            logo: "".to_string(),
+            ext: episode.ext(),
+            added: episode.added.clone(),
+            episode: Some(EpisodeInfo {
+                series_id: series.series_id.clone(),
+                series_name: series_name.to_string(),
+                season: episode.season.clone(),
+                cover: series.cover.clone(),
+            }),
+        }
+    }
+
+    // Episodes are listed under the name of their series
+    pub fn group_title(&self) -> &str {
+        match &self.episode {
+            Some(e) => &e.series_name,
+            None => &self.category,
+        }
+    }
+}
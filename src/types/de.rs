@@ -0,0 +1,112 @@
+// Lenient deserializers for fields that providers send as either strings or numbers
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use serde::de::{DeserializeOwned, Error};
+use serde::{Deserialize, Deserializer};
+use serde_json::Value;
+
+pub fn string_or_number<'de, D: Deserializer<'de>>(d: D) -> Result<String, D::Error> {
+    match Option::<Value>::deserialize(d)? {
+        None | Some(Value::Null) => Ok(String::new()),
+        Some(Value::String(s)) => Ok(s),
+        Some(Value::Number(n)) => Ok(n.to_string()),
+        Some(Value::Bool(b)) => Ok(b.to_string()),
+        Some(other) => Err(D::Error::custom(format!(
+            "expected a string or number, found {other}"
+        ))),
+    }
+}
+
+pub fn opt_string_or_number<'de, D: Deserializer<'de>>(d: D) -> Result<Option<String>, D::Error> {
+    let s = string_or_number(d)?;
+    Ok(match s.is_empty() {
+        true => None,
+        false => Some(s),
+    })
+}
+
+// Like string_or_number, but a missing or empty id is an error
+pub fn id<'de, D: Deserializer<'de>>(d: D) -> Result<String, D::Error> {
+    match string_or_number(d)? {
+        s if s.is_empty() => Err(D::Error::custom("missing id")),
+        s => Ok(s),
+    }
+}
+
+pub fn opt_int_or_string<'de, D: Deserializer<'de>>(d: D) -> Result<Option<i64>, D::Error> {
+    match Option::<Value>::deserialize(d)? {
+        None | Some(Value::Null) => Ok(None),
+        Some(Value::Number(n)) => match n.as_i64() {
+            Some(i) => Ok(Some(i)),
+            None => Ok(n.as_f64().map(|f| f as i64)),
+        },
+        Some(Value::String(s)) if s.trim().is_empty() => Ok(None),
+        Some(Value::String(s)) => s
+            .trim()
+            .parse()
+            .map(Some)
+            .map_err(|_| D::Error::custom(format!("expected a number, found {s:?}"))),
+        Some(other) => Err(D::Error::custom(format!(
+            "expected a number, found {other}"
+        ))),
+    }
+}
+
+pub fn int_or_string<'de, D: Deserializer<'de>>(d: D) -> Result<i64, D::Error> {
+    Ok(opt_int_or_string(d)?.unwrap_or_default())
+}
+
+pub fn opt_bool_or_string<'de, D: Deserializer<'de>>(d: D) -> Result<Option<bool>, D::Error> {
+    match Option::<Value>::deserialize(d)? {
+        None | Some(Value::Null) => Ok(None),
+        Some(Value::Bool(b)) => Ok(Some(b)),
+        Some(Value::Number(n)) => Ok(Some(n.as_i64().unwrap_or_default() != 0)),
+        Some(Value::String(s)) => Ok(Some(matches!(s.trim(), "1" | "true" | "True" | "yes"))),
+        Some(other) => Err(D::Error::custom(format!(
+            "expected a boolean, found {other}"
+        ))),
+    }
+}
+
+pub fn bool_or_string<'de, D: Deserializer<'de>>(d: D) -> Result<bool, D::Error> {
+    Ok(opt_bool_or_string(d)?.unwrap_or_default())
+}
+
+// Some providers send a single string instead of a list
+pub fn string_list<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<String>, D::Error> {
+    match Option::<Value>::deserialize(d)? {
+        None | Some(Value::Null) => Ok(vec![]),
+        Some(Value::String(s)) => Ok(vec![s]),
+        Some(Value::Array(a)) => Ok(a
+            .into_iter()
+            .map(|v| match v {
+                Value::String(s) => s,
+                other => other.to_string(),
+            })
+            .collect()),
+        Some(other) => Err(D::Error::custom(format!("expected a list, found {other}"))),
+    }
+}
+
+// Some providers send [] instead of an empty object
+pub fn object<'de, D, T>(d: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: DeserializeOwned + Default,
+{
+    match Option::<Value>::deserialize(d)? {
+        Some(v @ Value::Object(_)) => serde_json::from_value(v).map_err(D::Error::custom),
+        _ => Ok(T::default()),
+    }
+}
+
+// Text that get_short_epg sends base64 encoded. Anything that does not decode is kept as it is,
+// as some providers send plain text.
+pub fn base64_string<'de, D: Deserializer<'de>>(d: D) -> Result<String, D::Error> {
+    let s = string_or_number(d)?;
+    Ok(STANDARD
+        .decode(s.trim())
+        .ok()
+        .and_then(|b| String::from_utf8(b).ok())
+        .unwrap_or(s))
+}
@@ -0,0 +1,51 @@
+use serde::{Deserialize, Serialize};
+
+use crate::types::de;
+
+// A programme from get_short_epg
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct EpgListing {
+    #[serde(default, deserialize_with = "de::string_or_number")]
+    pub epg_id: String,
+    #[serde(default, deserialize_with = "de::base64_string")]
+    pub title: String,
+    #[serde(default, deserialize_with = "de::base64_string")]
+    pub description: String,
+    #[serde(default, deserialize_with = "de::string_or_number")]
+    pub start: String,
+    #[serde(default, deserialize_with = "de::string_or_number")]
+    pub end: String,
+    #[serde(default, deserialize_with = "de::opt_int_or_string")]
+    pub start_timestamp: Option<i64>,
+    #[serde(default, deserialize_with = "de::opt_int_or_string")]
+    pub stop_timestamp: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct ShortEpg {
+    #[serde(default)]
+    pub epg_listings: Vec<EpgListing>,
+}
+
+impl ShortEpg {
+    // The programme running at the given time and the one after it. Providers usually start
+    // the list with the current programme, but not always.
+    pub fn now_next(&self, now: i64) -> (Option<&EpgListing>, Option<&EpgListing>) {
+        let mut listings: Vec<&EpgListing> = self
+            .epg_listings
+            .iter()
+            .filter(|l| l.start_timestamp.is_some())
+            .collect();
+        listings.sort_by_key(|l| l.start_timestamp);
+        let current = listings.iter().position(|l| {
+            l.start_timestamp.is_some_and(|s| s <= now) && l.stop_timestamp.is_none_or(|s| now < s)
+        });
+        let next = match current {
+            Some(i) => listings.get(i + 1),
+            None => listings
+                .iter()
+                .find(|l| l.start_timestamp.is_some_and(|s| s > now)),
+        };
+        (current.map(|i| listings[i]), next.copied())
+    }
+}
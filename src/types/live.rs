@@ -0,0 +1,25 @@
+use serde::{Deserialize, Serialize};
+
+use crate::types::de;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LiveStream {
+    #[serde(default, deserialize_with = "de::opt_int_or_string")]
+    pub num: Option<i64>,
+    #[serde(default, deserialize_with = "de::string_or_number")]
+    pub name: String,
+    #[serde(deserialize_with = "de::id")]
+    pub stream_id: String,
+    #[serde(default, deserialize_with = "de::string_or_number")]
+    pub stream_icon: String,
+    #[serde(default, deserialize_with = "de::string_or_number")]
+    pub epg_channel_id: String,
+    #[serde(default, deserialize_with = "de::string_or_number")]
+    pub added: String,
+    #[serde(default, deserialize_with = "de::opt_string_or_number")]
+    pub category_id: Option<String>,
+    #[serde(default, deserialize_with = "de::bool_or_string")]
+    pub tv_archive: bool,
+    #[serde(default, deserialize_with = "de::opt_int_or_string")]
+    pub tv_archive_duration: Option<i64>,
+}
@@ -1 +1,80 @@
-pub mod series;
\ No newline at end of file
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+pub mod account;
+pub mod category;
+pub mod channel;
+pub mod de;
+pub mod epg;
+pub mod live;
+pub mod series;
+pub mod vod;
+
+pub use account::{AccountInfo, ServerInfo, UserInfo};
+pub use category::Category;
+pub use channel::Channel;
+pub use epg::{EpgListing, ShortEpg};
+pub use live::LiveStream;
+pub use series::SeriesStream;
+pub use vod::{MovieData, VodDetails, VodInfo, VodStream};
+
+pub trait HasCategory {
+    fn category_id(&self) -> Option<&str>;
+}
+
+impl HasCategory for LiveStream {
+    fn category_id(&self) -> Option<&str> {
+        self.category_id.as_deref()
+    }
+}
+
+impl HasCategory for VodStream {
+    fn category_id(&self) -> Option<&str> {
+        self.category_id.as_deref()
+    }
+}
+
+impl HasCategory for SeriesStream {
+    fn category_id(&self) -> Option<&str> {
+        self.category_id.as_deref()
+    }
+}
+
+// An entry from a provider list that could not be parsed
+#[derive(Debug, Clone)]
+pub struct Skipped {
+    pub name: String,
+    pub error: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct Listing<T> {
+    pub items: Vec<T>,
+    pub skipped: Vec<Skipped>,
+}
+
+impl<T: DeserializeOwned> Listing<T> {
+    // Parse each entry on its own so one bad entry does not lose the whole list
+    pub fn parse(values: Vec<Value>) -> Listing<T> {
+        let mut items = Vec::with_capacity(values.len());
+        let mut skipped = vec![];
+        for (i, v) in values.into_iter().enumerate() {
+            let name = match v["name"]
+                .as_str()
+                .or(v["category_name"].as_str())
+                .or(v["title"].as_str())
+            {
+                Some(n) => n.to_string(),
+                None => format!("entry {i}"),
+            };
+            match serde_json::from_value(v) {
+                Ok(item) => items.push(item),
+                Err(e) => skipped.push(Skipped {
+                    name,
+                    error: e.to_string(),
+                }),
+            }
+        }
+        Listing { items, skipped }
+    }
+}
@@ -2,16 +2,41 @@ use serde::{Deserialize, Serialize};
 use serde_json::{Value};
 use std::collections::HashMap;
 
+use crate::types::{Listing, de};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SeriesStream {
+    #[serde(default, deserialize_with = "de::opt_int_or_string")]
+    pub num: Option<i64>,
+    #[serde(default, deserialize_with = "de::string_or_number")]
+    pub name: String,
+    #[serde(deserialize_with = "de::id")]
+    pub series_id: String,
+    #[serde(default, deserialize_with = "de::string_or_number")]
+    pub cover: String,
+    #[serde(default, deserialize_with = "de::opt_string_or_number")]
+    pub category_id: Option<String>,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Episode {
+    #[serde(deserialize_with = "de::id")]
     pub id: String,
+    #[serde(default)]
     episode_num: Value,
+    #[serde(default, deserialize_with = "de::string_or_number")]
     pub title: String,
+    #[serde(default, deserialize_with = "de::string_or_number")]
     pub container_extension: String,
+    #[serde(default)]
     pub info: Value,
+    #[serde(default)]
     custom_sid: Value,
-    added: String,
-    season: Value,
+    #[serde(default, deserialize_with = "de::string_or_number")]
+    pub added: String,
+    #[serde(default, deserialize_with = "de::string_or_number")]
+    pub season: String,
+    #[serde(default, deserialize_with = "de::string_or_number")]
     direct_source: String
 }
 
@@ -27,22 +52,22 @@ impl EpisodeTrait for Episode {
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Series {
-    seasons: Vec<Value>,
-    info: Value,
-    episodes: Value,
+    pub seasons: Vec<Value>,
+    pub info: Value,
+    pub episodes: Value,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct SeriesVec {
-    seasons: Vec<Value>,
-    info: Value,
-    episodes: Vec<Vec<Value>>,
+    pub seasons: Vec<Value>,
+    pub info: Value,
+    pub episodes: Vec<Vec<Value>>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct SeriesEmpty {
-    seasons: Vec<Value>,
-    info: Value,
+    pub seasons: Vec<Value>,
+    pub info: Value,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -53,8 +78,29 @@ pub enum SerEnum {
     None,
 }
 
+impl SerEnum {
+    // The series metadata, such as name, cover and plot
+    pub fn info(&self) -> Option<&Value> {
+        match self {
+            SerEnum::Series(s) => Some(&s.info),
+            SerEnum::SeriesVec(s) => Some(&s.info),
+            SerEnum::SeriesEmpty(s) => Some(&s.info),
+            SerEnum::None => None,
+        }
+    }
+
+    pub fn seasons(&self) -> &[Value] {
+        match self {
+            SerEnum::Series(s) => &s.seasons,
+            SerEnum::SeriesVec(s) => &s.seasons,
+            SerEnum::SeriesEmpty(s) => &s.seasons,
+            SerEnum::None => &[],
+        }
+    }
+}
+
 pub trait Episodes {
-    fn get_episodes(&self) -> Vec<Episode>;
+    fn get_episodes(&self) -> Listing<Episode>;
     fn series_name(&self) -> String;
 }
 
@@ -70,36 +116,32 @@ impl Episodes for SerEnum {
         }
     }
     
-    fn get_episodes(&self) -> Vec<Episode> {
+    // Episodes that cannot be read are skipped, not a reason to lose the whole series
+    fn get_episodes(&self) -> Listing<Episode> {
         let mut elist = Vec::new();
         match self {
             SerEnum::Series(series) => {
                 let e = series.episodes.to_string();
-                let e_values: HashMap<String, Value> = serde_json::from_str(&e).expect("JSON");
-                let mut episodes: Vec<_> = e_values.iter().collect();
-                episodes.sort_by_key(|a| a.0);
-                for (_k, v) in episodes.iter() {
-                    for i in v.as_array().unwrap() {
-                        let ep: Episode = serde_json::from_value(i.to_owned()).unwrap();
-                        elist.push(ep);
+                let e_values: HashMap<String, Value> = serde_json::from_str(&e).unwrap_or_default();
+                let mut episodes: Vec<_> = e_values.into_iter().collect();
+                episodes.sort_by(|a, b| a.0.cmp(&b.0));
+                for (_k, v) in episodes {
+                    if let Value::Array(a) = v {
+                        elist.extend(a);
                     }
                 }
-                elist
             },
             SerEnum::SeriesVec(series) => {
                 for e in series.episodes.iter() {
-                    for e1 in e.iter() {
-                        let ep: Episode = serde_json::from_value(e1.to_owned()).unwrap();
-                        elist.push(ep);
-                    }
+                    elist.extend(e.iter().cloned());
                 }
-                elist
             },
-            _ => {
-                elist}
+            _ => {}
         }
+        Listing::parse(elist)
     }
 }
+
 pub fn read_series(s: String) -> SerEnum{
     match serde_json::from_str::<SeriesVec>(&s) {
         Ok(x) => SerEnum::SeriesVec(x),
@@ -109,10 +151,7 @@ pub fn read_series(s: String) -> SerEnum{
                 Err(_) => {
                     match serde_json::from_str::<SeriesEmpty>(&s) {
                         Ok(x) => SerEnum::SeriesEmpty(x),
-                        _ => {
-                            println!("Unable to parse");
-                            SerEnum::None
-                        },
+                        _ => SerEnum::None,
                     }
                 }
             }
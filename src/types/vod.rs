@@ -0,0 +1,97 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::types::de;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct VodStream {
+    #[serde(default, deserialize_with = "de::opt_int_or_string")]
+    pub num: Option<i64>,
+    #[serde(default, deserialize_with = "de::string_or_number")]
+    pub name: String,
+    #[serde(deserialize_with = "de::id")]
+    pub stream_id: String,
+    #[serde(default, deserialize_with = "de::string_or_number")]
+    pub stream_icon: String,
+    #[serde(default, deserialize_with = "de::string_or_number")]
+    pub rating: String,
+    #[serde(default, deserialize_with = "de::string_or_number")]
+    pub added: String,
+    #[serde(default, deserialize_with = "de::opt_string_or_number")]
+    pub category_id: Option<String>,
+    #[serde(default, deserialize_with = "de::string_or_number")]
+    pub container_extension: String,
+}
+
+impl VodStream {
+    pub fn ext(&self) -> String {
+        match self.container_extension.is_empty() {
+            true => "".to_string(),
+            false => format!(".{}", self.container_extension),
+        }
+    }
+}
+
+// The details of a movie from get_vod_info. The metadata differs between providers, so only
+// the common fields are read and the rest is kept in extra.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct VodInfo {
+    #[serde(default, deserialize_with = "de::object")]
+    pub info: VodDetails,
+    #[serde(default, deserialize_with = "de::object")]
+    pub movie_data: Option<MovieData>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct VodDetails {
+    #[serde(default, deserialize_with = "de::string_or_number")]
+    pub name: String,
+    #[serde(default, deserialize_with = "de::string_or_number")]
+    pub o_name: String,
+    #[serde(default, deserialize_with = "de::string_or_number")]
+    pub cover_big: String,
+    #[serde(default, deserialize_with = "de::string_or_number")]
+    pub movie_image: String,
+    #[serde(default, deserialize_with = "de::string_list")]
+    pub backdrop_path: Vec<String>,
+    #[serde(default, deserialize_with = "de::string_or_number")]
+    pub releasedate: String,
+    #[serde(default, deserialize_with = "de::string_or_number")]
+    pub genre: String,
+    #[serde(default, deserialize_with = "de::string_or_number")]
+    pub director: String,
+    #[serde(default, deserialize_with = "de::string_or_number")]
+    pub cast: String,
+    #[serde(default, deserialize_with = "de::string_or_number")]
+    pub plot: String,
+    #[serde(default, deserialize_with = "de::string_or_number")]
+    pub country: String,
+    #[serde(default, deserialize_with = "de::string_or_number")]
+    pub rating: String,
+    #[serde(default, deserialize_with = "de::opt_int_or_string")]
+    pub duration_secs: Option<i64>,
+    #[serde(default, deserialize_with = "de::string_or_number")]
+    pub duration: String,
+    #[serde(default, deserialize_with = "de::string_or_number")]
+    pub youtube_trailer: String,
+    #[serde(default, deserialize_with = "de::opt_string_or_number")]
+    pub tmdb_id: Option<String>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MovieData {
+    #[serde(deserialize_with = "de::id")]
+    pub stream_id: String,
+    #[serde(default, deserialize_with = "de::string_or_number")]
+    pub name: String,
+    #[serde(default, deserialize_with = "de::string_or_number")]
+    pub added: String,
+    #[serde(default, deserialize_with = "de::opt_string_or_number")]
+    pub category_id: Option<String>,
+    #[serde(default, deserialize_with = "de::string_or_number")]
+    pub container_extension: String,
+    #[serde(default, deserialize_with = "de::string_or_number")]
+    pub direct_source: String,
+}